@@ -1,26 +1,299 @@
-// Parse a whole CSS Stylesheet.
-pub fn parse(source: String) -> Stylesheet {
-  let mut parser = Parser {
-    position: 0,
-    input: source,
-  };
-  Stylesheet {
-    rules: parser.parse_rules(),
+use super::location;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::css_tokenizer::Tokenizer;
+pub use super::css_tokenizer::{tokenize, CssToken};
+
+// Parse a whole CSS Stylesheet, failing on the first error.
+pub fn parse(source: String) -> Result<Stylesheet, ParseError> {
+  let (stylesheet, mut errors) = parse_with_errors(source);
+  if errors.is_empty() {
+    Ok(stylesheet)
+  } else {
+    Err(errors.remove(0))
+  }
+}
+
+// Parse a whole CSS Stylesheet the way browsers do: invalid declarations and rules
+// are dropped and parsing resumes after them. Returns the errors that were skipped.
+pub fn parse_with_errors(source: String) -> (Stylesheet, Vec<ParseError>) {
+  let mut parser = Parser::new(source);
+  let rules = parser.parse_items();
+  (Stylesheet { rules }, parser.errors)
+}
+
+// Parse a comma-separated list of selectors on its own, e.g. `ul > li, p.note`.
+pub fn parse_selector_list(source: String) -> Result<Vec<Selector>, ParseError> {
+  let mut parser = Parser::new(source);
+  parser
+    .parse_selector_list()
+    .map_err(|error| parser.locate_error(error))
+}
+
+// Replace `var(--name)` values with the value of the custom property `--name`.
+// Custom properties are collected from every rule in the stylesheet, later ones winning,
+// instead of being inherited through the DOM. This is a single pass, so variables that
+// appear in a substituted value are left as they are.
+pub fn resolve_variables(stylesheet: &Stylesheet) -> Stylesheet {
+  let mut variables = HashMap::new();
+  for item in &stylesheet.rules {
+    let rules = match *item {
+      CssItem::Rule(ref rule) => std::slice::from_ref(rule),
+      CssItem::AtRule(AtRule::Media(ref media)) => &media.rules[..],
+      CssItem::AtRule(_) => &[],
+    };
+    for declaration in rules.iter().flat_map(|rule| &rule.declarations) {
+      if declaration.name.starts_with("--") {
+        variables.insert(declaration.name.clone(), declaration.value.clone());
+      }
+    }
+  }
+
+  let mut resolved = stylesheet.clone();
+  for item in &mut resolved.rules {
+    let declarations: Vec<&mut Declaration> = match *item {
+      CssItem::Rule(ref mut rule) => rule.declarations.iter_mut().collect(),
+      CssItem::AtRule(AtRule::Media(ref mut media)) => media
+        .rules
+        .iter_mut()
+        .flat_map(|rule| rule.declarations.iter_mut())
+        .collect(),
+      CssItem::AtRule(AtRule::Keyframes(ref mut keyframes)) => keyframes
+        .keyframes
+        .iter_mut()
+        .flat_map(|keyframe| keyframe.declarations.iter_mut())
+        .collect(),
+      CssItem::AtRule(AtRule::FontFace(ref mut font_face)) => {
+        font_face.declarations.iter_mut().collect()
+      }
+      CssItem::AtRule(AtRule::Import(_)) => Vec::new(),
+    };
+    for declaration in declarations {
+      declaration.value = substitute_variables(&declaration.value, &variables);
+    }
+  }
+  resolved
+}
+
+// Replace the variables in `value`, falling back when a variable is not defined.
+// A variable that is not defined and has no fallback is left as it is.
+fn substitute_variables(value: &Value, variables: &HashMap<String, Value>) -> Value {
+  match *value {
+    Value::Variable(ref name) => variables.get(name).cloned().unwrap_or_else(|| value.clone()),
+    Value::VariableWithFallback(ref name, ref fallback) => match variables.get(name) {
+      Some(variable) => variable.clone(),
+      None => (**fallback).clone(),
+    },
+    Value::List(ref values) => Value::List(
+      values
+        .iter()
+        .map(|value| substitute_variables(value, variables))
+        .collect(),
+    ),
+    Value::CommaList(ref values) => Value::CommaList(
+      values
+        .iter()
+        .map(|value| substitute_variables(value, variables))
+        .collect(),
+    ),
+    _ => value.clone(),
+  }
+}
+
+// Errors that can occur while parsing a stylesheet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssError {
+  UnexpectedChar(char),
+  UnexpectedEof,
+  UnrecognizedUnit(String),
+  InvalidHexColor(String),
+  InvalidNumber(String),
+  UnknownFunction(String),
+  UnknownAtRule(String),
+  InvalidKeyframes(String),
+  ColorOutOfRange(f32),
+  WrongArgumentCount(String, usize),
+  InvalidArgument(String),
+  UnknownPseudoClass(String),
+  UnexpectedPseudoElement(String),
+  UnterminatedComment,
+  InvalidAnnotation(String),
+  InvalidShorthand(String),
+  MisplacedImport,
+}
+
+impl fmt::Display for CssError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CssError::UnexpectedChar(c) => write!(f, "unexpected character '{}'", c),
+      CssError::UnexpectedEof => write!(f, "unexpected end of input"),
+      CssError::UnrecognizedUnit(unit) => write!(f, "unrecognized unit '{}'", unit),
+      CssError::InvalidHexColor(s) => write!(f, "invalid hex color '{}'", s),
+      CssError::InvalidNumber(s) => write!(f, "invalid number '{}'", s),
+      CssError::UnknownFunction(name) => write!(f, "unknown function '{}()'", name),
+      CssError::UnknownAtRule(name) => write!(f, "unknown at-rule '@{}'", name),
+      CssError::InvalidKeyframes(s) => write!(f, "invalid keyframes '{}'", s),
+      CssError::ColorOutOfRange(n) => write!(f, "color component {} is out of range", n),
+      CssError::WrongArgumentCount(name, n) => {
+        write!(f, "wrong number of arguments ({}) to '{}()'", n, name)
+      }
+      CssError::InvalidArgument(name) => write!(f, "invalid argument to '{}()'", name),
+      CssError::UnknownPseudoClass(name) => write!(f, "unknown pseudo-class ':{}'", name),
+      CssError::UnexpectedPseudoElement(name) => write!(f, "unexpected pseudo-element '::{}'", name),
+      CssError::UnterminatedComment => write!(f, "unterminated comment"),
+      CssError::InvalidAnnotation(s) => write!(f, "invalid annotation '!{}'", s),
+      CssError::InvalidShorthand(name) => write!(f, "invalid value for shorthand '{}'", name),
+      CssError::MisplacedImport => write!(f, "@import after other rules"),
+    }
+  }
+}
+
+impl std::error::Error for CssError {}
+
+// A `CssError` along with where in the source it was found: the byte offset, and the
+// 1-based line and column.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+  pub error: CssError,
+  pub position: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at line {}, column {}", self.error, self.line, self.column)
   }
 }
 
-// A CSS stylesheet is a series of rules.
-#[derive(Debug)]
+impl std::error::Error for ParseError {}
+
+// A CSS stylesheet is a series of rules and at-rules.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stylesheet {
-  rules: Vec<Rule>,
+  pub rules: Vec<CssItem>,
+}
+
+impl Stylesheet {
+  // Serialize the stylesheet back into CSS, see `impl fmt::Display for Stylesheet`.
+  pub fn to_css(&self) -> String {
+    self.to_string()
+  }
+
+  // The top-level style rules, leaving out at-rules and the rules nested in them.
+  pub fn style_rules(&self) -> impl Iterator<Item = &Rule> {
+    self.rules.iter().filter_map(|item| match *item {
+      CssItem::Rule(ref rule) => Some(rule),
+      CssItem::AtRule(_) => None,
+    })
+  }
+
+  // The URLs of the stylesheets imported with `@import`, in order. They aren't fetched, so
+  // resolving and loading them is left to the caller.
+  pub fn imports(&self) -> impl Iterator<Item = &str> {
+    self.rules.iter().filter_map(|item| match *item {
+      CssItem::AtRule(AtRule::Import(ref import)) => Some(&*import.url),
+      _ => None,
+    })
+  }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CssItem {
+  Rule(Rule),
+  AtRule(AtRule),
+}
+
+// At-rules start with '@' and change how a group of rules is applied.
+// See: https://www.w3.org/TR/css-syntax-3/#at-rules
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AtRule {
+  Import(ImportRule),
+  Media(MediaRule),
+  Keyframes(KeyframesRule),
+  FontFace(FontFaceRule),
+}
+
+// `@import url(<url>) [<query>[, <query>]*];` or `@import "<url>" ...;`
+// Imports have to come before any other rules.
+// See: https://www.w3.org/TR/css-cascade-4/#at-import
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImportRule {
+  pub url: String,
+  // The imported stylesheet only applies if any of these match, or always if there are none.
+  pub queries: Vec<MediaQuery>,
+}
+
+// `@media <query>[, <query>]* { <rules> }`
+// The rules apply if any of the queries match.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MediaRule {
+  pub queries: Vec<MediaQuery>,
+  pub rules: Vec<Rule>,
+}
+
+// `@keyframes <name> { <keyframes> }`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyframesRule {
+  pub name: String,
+  pub keyframes: Vec<Keyframe>,
+}
+
+// One step of an animation, e.g. `from, 50% { opacity: 0; }`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keyframe {
+  pub selectors: Vec<KeyframeSelector>,
+  pub declarations: Vec<Declaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KeyframeSelector {
+  From, // Same as `0%`.
+  To,   // Same as `100%`.
+  Percentage(f32),
+}
+
+// `@font-face { <declarations> }`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FontFaceRule {
+  pub declarations: Vec<Declaration>,
+}
+
+// A media query is an optional media type followed by conditions joined with `and`,
+// e.g. `screen and (min-width: 320px)`.
+// See: https://www.w3.org/TR/mediaqueries-4/#media
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MediaQuery {
+  pub media_type: Option<String>,
+  pub conditions: Vec<MediaCondition>,
+}
+
+// A media feature test such as `(max-width: 768px)`, or `(color)` without a value.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MediaCondition {
+  pub feature: String,
+  pub value: Option<Value>,
 }
 
 // A rule includes one or more selectors separated by commas,
 // followed by a series of declarations enclosed in braces.
-#[derive(Debug)]
-struct Rule {
-  selectors: Vec<Selector>,
-  declarations: Vec<Declaration>,
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+  pub selectors: Vec<Selector>,
+  pub declarations: Vec<Declaration>,
 }
 
 // Specifity is one of the ways a rendering engine decades which style overrides the other in a conflict.
@@ -28,258 +301,2807 @@ struct Rule {
 pub type Specificity = (usize, usize, usize);
 
 // See CSS selectors syntax here: https://www.w3.org/TR/CSS2/selector.html#selector-syntax
-// In this project, a simple selector is only implemented for simplicity.
-#[derive(Debug)]
-enum Selector {
+// A selector is either a single simple selector, or a chain of simple selectors
+// joined by combinators, e.g. `div.note > p`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Selector {
   Simple(SimpleSelector),
+  Complex(ComplexSelector),
 }
 
 impl Selector {
   pub fn specificity(&self) -> Specificity {
-    let Selector::Simple(ref simple) = *self;
-    let a = simple.id.iter().count();
-    let b = simple.class.len();
-    let c = simple.tag_name.iter().count();
+    match *self {
+      Selector::Simple(ref simple) => simple.specificity(),
+      Selector::Complex(ref complex) => complex.specificity(),
+    }
+  }
+}
+
+// Each simple selector after the first is paired with the combinator that precedes it,
+// so `div.note > p` is `div.note` followed by `(Child, p)`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComplexSelector {
+  pub first: SimpleSelector,
+  pub rest: Vec<(Combinator, SimpleSelector)>,
+}
 
-    (a, b, c)
+impl ComplexSelector {
+  // The specificity of a complex selector is the sum of the specificities of its parts.
+  pub fn specificity(&self) -> Specificity {
+    self
+      .rest
+      .iter()
+      .map(|(_, simple)| simple.specificity())
+      .fold(self.first.specificity(), |(a, b, c), (x, y, z)| {
+        (a + x, b + y, c + z)
+      })
   }
 }
 
+// See combinators here: https://www.w3.org/TR/selectors/#combinators
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Combinator {
+  Descendant,      // `A B`
+  Child,           // `A > B`
+  AdjacentSibling, // `A + B`
+  GeneralSibling,  // `A ~ B`
+}
+
 // In this project, a simple selector can include a tag name, an ID prefixed by '#',
-// any number of class names  prefixed by '.', or some combination of the above.
-#[derive(Debug)]
-struct SimpleSelector {
-  tag_name: Option<String>,
-  id: Option<String>,
-  class: Vec<String>,
+// any number of class names  prefixed by '.', any number of attribute selectors
+// enclosed in '[...]', any number of pseudo-classes prefixed by ':', one pseudo-element
+// prefixed by '::', or some combination of the above.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SimpleSelector {
+  pub tag_name: Option<String>,
+  pub id: Option<String>,
+  pub class: Vec<String>,
+  pub attributes: Vec<AttributeSelector>,
+  pub pseudo_classes: Vec<PseudoClass>,
+  pub pseudo_element: Option<PseudoElement>,
+}
+
+impl SimpleSelector {
+  pub fn specificity(&self) -> Specificity {
+    let a = self.id.iter().count();
+    let b = self.class.len() + self.attributes.len();
+    let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+
+    // Each pseudo-class counts like a class, except that `:not()` counts as its argument.
+    self
+      .pseudo_classes
+      .iter()
+      .map(|pseudo_class| match *pseudo_class {
+        PseudoClass::Not(ref selector) => selector.specificity(),
+        _ => (0, 1, 0),
+      })
+      .fold((a, b, c), |(a, b, c), (x, y, z)| (a + x, b + y, c + z))
+  }
+}
+
+// An attribute selector tests an element's attribute, e.g. `[type="text"]`.
+// Without an operator it only checks that the attribute is present.
+// See: https://www.w3.org/TR/selectors/#attribute-selectors
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeSelector {
+  pub name: String,
+  pub op: Option<AttrOp>,
+  pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AttrOp {
+  Exact,     // `[attr=value]`
+  Includes,  // `[attr~=value]`: one of its whitespace-separated words is `value`.
+  DashMatch, // `[attr|=value]`: it is `value` or starts with `value-`.
+  Prefix,    // `[attr^=value]`
+  Suffix,    // `[attr$=value]`
+  Substring, // `[attr*=value]`
+}
+
+// See pseudo-classes here: https://www.w3.org/TR/selectors/#pseudo-classes
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PseudoClass {
+  Hover,
+  Focus,
+  Active,
+  Visited,
+  Checked,
+  Disabled,
+  Enabled,
+  FirstChild,
+  Not(Box<SimpleSelector>), // `:not(selector)`
+}
+
+// See pseudo-elements here: https://www.w3.org/TR/selectors/#pseudo-elements
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PseudoElement {
+  Before,
+  After,
+  FirstLine,
+  FirstLetter,
+  Placeholder,
+  Selection,
+}
+
+impl PseudoElement {
+  // Pseudo-elements from CSS 2 may also be written with a single colon, e.g. `:before`.
+  fn allows_single_colon(&self) -> bool {
+    matches!(
+      *self,
+      PseudoElement::Before
+        | PseudoElement::After
+        | PseudoElement::FirstLine
+        | PseudoElement::FirstLetter
+    )
+  }
 }
 
 // A declaration is just a name/value pair, separated by a colon and ending with a semicolon.
-#[derive(Debug)]
-struct Declaration {
-  name: String,
-  value: Value,
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Declaration {
+  pub name: String,
+  pub value: Value,
+  pub important: bool, // Set by a trailing `!important`.
 }
 
-#[derive(Debug)]
-enum Value {
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
   Keyword(String),
-  Length(f32, Unit), // f32 is an 32-bit float.
+  String(String),        // A quoted string, e.g. `"Open Sans"`.
+  Number(f32),           // A number without a unit, e.g. `opacity: 0.5`.
+  Length(f32, Unit),     // f32 is an 32-bit float.
   ColorValue(Color),
+  Url(String),           // `url(font.woff2)`
+  Format(String),        // `format("woff2")`, used in `@font-face` sources.
+  List(Vec<Value>),      // Space-separated values, e.g. `url(a.woff) format("woff")`.
+  CommaList(Vec<Value>), // Comma-separated values, e.g. `Arial, sans-serif`.
+  Variable(String),      // `var(--name)`
+  VariableWithFallback(String, Box<Value>), // `var(--name, fallback)`
+  Calc(CalcExpr),        // `calc(100% - 20px)`
+  BorderStyle(BorderStyle),
+}
+
+// A math expression inside `calc()`. `*` and `/` bind tighter than `+` and `-`.
+// See: https://www.w3.org/TR/css-values-3/#calc-notation
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CalcExpr {
+  Number(f32),
+  Length(f32, Unit),
+  Percentage(f32),
+  Add(Box<CalcExpr>, Box<CalcExpr>),
+  Sub(Box<CalcExpr>, Box<CalcExpr>),
+  Mul(Box<CalcExpr>, Box<CalcExpr>),
+  Div(Box<CalcExpr>, Box<CalcExpr>),
+}
+
+impl CalcExpr {
+  // Evaluate the expression in px, resolving lengths like `Value::to_px`.
+  // A bare number is used as it is, so `2 * 10px` is 20px.
+  pub fn to_px(&self, parent_px: f32, font_size: f32) -> f32 {
+    match *self {
+      CalcExpr::Number(number) => number,
+      CalcExpr::Length(length, ref unit) => length_to_px(length, unit, parent_px, font_size),
+      CalcExpr::Percentage(percentage) => percentage * parent_px / 100.0,
+      CalcExpr::Add(ref a, ref b) => a.to_px(parent_px, font_size) + b.to_px(parent_px, font_size),
+      CalcExpr::Sub(ref a, ref b) => a.to_px(parent_px, font_size) - b.to_px(parent_px, font_size),
+      CalcExpr::Mul(ref a, ref b) => a.to_px(parent_px, font_size) * b.to_px(parent_px, font_size),
+      CalcExpr::Div(ref a, ref b) => a.to_px(parent_px, font_size) / b.to_px(parent_px, font_size),
+    }
+  }
+}
+
+impl Value {
+  // Convert a length to px. Percentages are of `parent_px`, and font-relative units
+  // are relative to `font_size`. Anything that isn't a length, including the viewport
+  // units, which need a viewport, is zero.
+  pub fn to_px(&self, parent_px: f32, font_size: f32) -> f32 {
+    match *self {
+      Value::Length(length, ref unit) => length_to_px(length, unit, parent_px, font_size),
+      Value::Calc(ref expr) => expr.to_px(parent_px, font_size),
+      _ => 0.0,
+    }
+  }
+
+  // The values of a space-separated list, or the value itself if it isn't a list.
+  pub fn items(&self) -> &[Value] {
+    match *self {
+      Value::List(ref values) => values,
+      ref value => std::slice::from_ref(value),
+    }
+  }
+}
+
+// The font size that `medium` and the root element have, in px.
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+fn length_to_px(length: f32, unit: &Unit, parent_px: f32, font_size: f32) -> f32 {
+  match *unit {
+    Unit::Percent => length * parent_px / 100.0,
+    Unit::Em => length * font_size,
+    // The root font size isn't known here, so it is assumed to be the default.
+    Unit::Rem => length * DEFAULT_FONT_SIZE,
+    // Without font metrics, `ex` and `ch` are taken to be half an `em`.
+    Unit::Ex | Unit::Ch => length * font_size / 2.0,
+    ref unit => unit
+      .to_px_factor()
+      .map_or(0.0, |factor| (f64::from(length) * factor) as f32),
+  }
+}
+
+// See: https://www.w3.org/TR/CSS2/box.html#border-style-properties
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BorderStyle {
+  None,
+  Hidden,
+  Dotted,
+  Dashed,
+  Solid,
+  Double,
+  Groove,
+  Ridge,
+  Inset,
+  Outset,
 }
 
-#[derive(Debug)]
-enum Unit {
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Unit {
   Px,
+  Percent,
+  Em,
+  Rem,
+  Ex,
+  Ch,
+  Vh,
+  Vw,
+  Pt,
+  Pc,
+  Cm,
+  Mm,
+  In,
+  Q, // A quarter of a millimeter.
 }
 
-#[derive(Debug)]
-struct Color {
-  r: u8, // u8 is an 8-bit unsigned integer.
-  g: u8,
-  b: u8,
-  a: u8,
+impl Unit {
+  // How many pixels one of this unit is, for the absolute units.
+  // Relative units depend on a font size or the viewport, so they return `None`.
+  // See: https://www.w3.org/TR/css-values-3/#absolute-lengths
+  pub fn to_px_factor(&self) -> Option<f64> {
+    match *self {
+      Unit::Px => Some(1.0),
+      Unit::In => Some(96.0),
+      Unit::Cm => Some(96.0 / 2.54),
+      Unit::Mm => Some(96.0 / 25.4),
+      Unit::Q => Some(96.0 / 101.6),
+      Unit::Pt => Some(96.0 / 72.0),
+      Unit::Pc => Some(96.0 / 6.0),
+      Unit::Percent | Unit::Em | Unit::Rem | Unit::Ex | Unit::Ch | Unit::Vh | Unit::Vw => None,
+    }
+  }
 }
 
-struct Parser {
-  position: usize,
-  input: String,
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color {
+  pub r: u8, // u8 is an 8-bit unsigned integer.
+  pub g: u8,
+  pub b: u8,
+  pub a: u8,
 }
 
-impl Parser {
-  // Read the current character without consuming it.
-  fn next_char(&self) -> char {
-    self.input[self.position..].chars().next().unwrap()
+impl Color {
+  // `#rrggbb`, or `#rrggbbaa` if the color isn't opaque.
+  pub fn to_hex(&self) -> String {
+    let hex = format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b);
+    match self.a {
+      255 => hex,
+      a => format!("{}{:02x}", hex, a),
+    }
   }
 
-  // Return true if all input is consumed.
-  fn eof(&self) -> bool {
-    self.position >= self.input.len()
+  // `rgba(r, g, b, a)`, with the alpha from 0 to 1 rounded to three decimals.
+  pub fn to_css_rgba(&self) -> String {
+    let alpha = (f32::from(self.a) / 255.0 * 1000.0).round() / 1000.0;
+    format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, alpha)
   }
-  // Return the current character, and advance self.pos to the next character.
-  fn consume_char(&mut self) -> char {
-    let mut iterator = self.input[self.position..].char_indices(); // returns an iterator over the "char"s of a string slice, and their positions.
-    let (_, current_char) = iterator.next().unwrap();
-    let (next_position, _) = iterator.next().unwrap_or((1, ' '));
-    self.position += next_position;
+}
 
-    return current_char;
+// Serializing stylesheets back into CSS. Each rule goes on its own line, and colors are
+// written as hex.
+
+impl fmt::Display for Stylesheet {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for item in &self.rules {
+      writeln!(f, "{}", item)?;
+    }
+    Ok(())
   }
-  // Consume characters until `test` returns false
-  // See to know usage of "where" clause: https://doc.rust-lang.org/rust-by-example/generics/where.html
-  fn consume_while<F>(&mut self, test: F) -> String
-  where
-    F: Fn(char) -> bool,
-  {
-    let mut result = String::new();
-    while !self.eof() && test(self.next_char()) {
-      result.push(self.consume_char());
+}
+
+impl fmt::Display for CssItem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CssItem::Rule(rule) => write!(f, "{}", rule),
+      CssItem::AtRule(at_rule) => write!(f, "{}", at_rule),
     }
+  }
+}
 
-    return result;
+impl fmt::Display for AtRule {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AtRule::Import(import) => {
+        write!(f, "@import url({})", quote(&import.url))?;
+        if !import.queries.is_empty() {
+          write!(f, " ")?;
+          write_separated(f, &import.queries, ", ")?;
+        }
+        write!(f, ";")
+      }
+      AtRule::Media(media) => {
+        write!(f, "@media ")?;
+        write_separated(f, &media.queries, ", ")?;
+        write!(f, " {{")?;
+        for rule in &media.rules {
+          write!(f, " {}", rule)?;
+        }
+        write!(f, " }}")
+      }
+      AtRule::Keyframes(keyframes) => {
+        write!(f, "@keyframes {} {{", keyframes.name)?;
+        for keyframe in &keyframes.keyframes {
+          write!(f, " ")?;
+          write_separated(f, &keyframe.selectors, ", ")?;
+          write_declaration_block(f, &keyframe.declarations)?;
+        }
+        write!(f, " }}")
+      }
+      AtRule::FontFace(font_face) => {
+        write!(f, "@font-face")?;
+        write_declaration_block(f, &font_face.declarations)
+      }
+    }
   }
+}
 
-  // Consume and discard zero or more whitespace characters.
-  fn consume_whitespace(&mut self) {
-    self.consume_while(char::is_whitespace);
+impl fmt::Display for KeyframeSelector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      KeyframeSelector::From => write!(f, "from"),
+      KeyframeSelector::To => write!(f, "to"),
+      KeyframeSelector::Percentage(percentage) => write!(f, "{}%", percentage),
+    }
   }
+}
 
-  // Parse a list of rule sets, separated by optional whitespace.
-  fn parse_rules(&mut self) -> Vec<Rule> {
-    let mut rules = Vec::new();
-    loop {
-      self.consume_whitespace();
-      if self.eof() {
-        break;
+impl fmt::Display for MediaQuery {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(ref media_type) = self.media_type {
+      write!(f, "{}", media_type)?;
+      if !self.conditions.is_empty() {
+        write!(f, " and ")?;
       }
-      rules.push(self.parse_rule())
     }
-    rules
+    write_separated(f, &self.conditions, " and ")
   }
+}
 
-  // Parse a rule set: `<selectors> { <declaarations> }`
-  fn parse_rule(&mut self) -> Rule {
-    Rule {
-      selectors: self.parse_selectors(),
-      declarations: self.parse_declarations(),
+impl fmt::Display for MediaCondition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.value {
+      Some(ref value) => write!(f, "({}: {})", self.feature, value),
+      None => write!(f, "({})", self.feature),
     }
   }
+}
 
-  // Parse a comma-separated list of selectors.
-  fn parse_selectors(&mut self) -> Vec<Selector> {
-    let mut selectors = Vec::new();
-    loop {
-      selectors.push(Selector::Simple(self.parse_simple_selector()));
-      self.consume_whitespace();
+impl fmt::Display for Rule {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write_separated(f, &self.selectors, ", ")?;
+    write_declaration_block(f, &self.declarations)
+  }
+}
 
-      match self.next_char() {
-        ',' => {
-          self.consume_char();
-          self.consume_whitespace();
+impl fmt::Display for Selector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Selector::Simple(simple) => write!(f, "{}", simple),
+      Selector::Complex(complex) => {
+        write!(f, "{}", complex.first)?;
+        for (combinator, simple) in &complex.rest {
+          write!(f, "{}{}", combinator, simple)?;
         }
-        '{' => break,
-        c => panic!("Unexpected character {} in selector list", c),
+        Ok(())
       }
     }
-    // Return selectors with highest specifity first, for use in matching.
-    selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-    selectors
   }
+}
 
-  // Parse a list of declarations enclosed in `{ ... }`.
-  fn parse_declarations(&mut self) -> Vec<Declaration> {
-    assert_eq!(self.consume_char(), '{');
-    let mut declarations = Vec::new();
-    loop {
-      self.consume_whitespace();
-      if self.next_char() == '}' {
-        self.consume_char();
-        break;
-      }
-      declarations.push(self.parse_declaration());
+impl fmt::Display for Combinator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Combinator::Descendant => write!(f, " "),
+      Combinator::Child => write!(f, " > "),
+      Combinator::AdjacentSibling => write!(f, " + "),
+      Combinator::GeneralSibling => write!(f, " ~ "),
     }
-    declarations
   }
+}
 
-  // Parse one `<property>: <value>;` declaration.
-  fn parse_declaration(&mut self) -> Declaration {
-    let property_name = self.parse_identifier();
-    self.consume_whitespace();
-    assert_eq!(self.consume_char(), ':');
-    self.consume_whitespace();
-    let value = self.parse_value();
-    self.consume_whitespace();
-    assert_eq!(self.consume_char(), ';');
-    Declaration {
-      name: property_name,
-      value: value,
+impl fmt::Display for SimpleSelector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.tag_name {
+      Some(ref tag_name) => write!(f, "{}", tag_name)?,
+      None if self.specificity() == (0, 0, 0) => write!(f, "*")?,
+      None => {}
+    }
+    if let Some(ref id) = self.id {
+      write!(f, "#{}", id)?;
+    }
+    for class in &self.class {
+      write!(f, ".{}", class)?;
+    }
+    for attribute in &self.attributes {
+      write!(f, "{}", attribute)?;
     }
+    for pseudo_class in &self.pseudo_classes {
+      write!(f, ":{}", pseudo_class)?;
+    }
+    if let Some(ref pseudo_element) = self.pseudo_element {
+      write!(f, "::{}", pseudo_element)?;
+    }
+    Ok(())
   }
+}
 
-  // Methods for parsing values.
-  fn parse_value(&mut self) -> Value {
-    match self.next_char() {
-      '0'..='9' => self.parse_length(),
-      '#' => self.parse_color(),
-      _ => Value::Keyword(self.parse_identifier()),
+impl fmt::Display for AttributeSelector {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let op = match self.op {
+      None => return write!(f, "[{}]", self.name),
+      Some(AttrOp::Exact) => "=",
+      Some(AttrOp::Includes) => "~=",
+      Some(AttrOp::DashMatch) => "|=",
+      Some(AttrOp::Prefix) => "^=",
+      Some(AttrOp::Suffix) => "$=",
+      Some(AttrOp::Substring) => "*=",
+    };
+    let value = self.value.as_deref().unwrap_or("");
+    write!(f, "[{}{}{}]", self.name, op, quote(value))
+  }
+}
+
+impl fmt::Display for PseudoClass {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PseudoClass::Hover => write!(f, "hover"),
+      PseudoClass::Focus => write!(f, "focus"),
+      PseudoClass::Active => write!(f, "active"),
+      PseudoClass::Visited => write!(f, "visited"),
+      PseudoClass::Checked => write!(f, "checked"),
+      PseudoClass::Disabled => write!(f, "disabled"),
+      PseudoClass::Enabled => write!(f, "enabled"),
+      PseudoClass::FirstChild => write!(f, "first-child"),
+      PseudoClass::Not(selector) => write!(f, "not({})", selector),
     }
   }
+}
 
-  fn parse_length(&mut self) -> Value {
-    Value::Length(self.parse_float(), self.parse_unit())
+impl fmt::Display for PseudoElement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      PseudoElement::Before => "before",
+      PseudoElement::After => "after",
+      PseudoElement::FirstLine => "first-line",
+      PseudoElement::FirstLetter => "first-letter",
+      PseudoElement::Placeholder => "placeholder",
+      PseudoElement::Selection => "selection",
+    };
+    write!(f, "{}", name)
   }
+}
 
-  fn parse_float(&mut self) -> f32 {
-    let s = self.consume_while(|c| match c {
-      '0'..='9' | '.' => true,
-      _ => false,
-    });
-    s.parse().unwrap()
+impl fmt::Display for Declaration {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.name, self.value)?;
+    if self.important {
+      write!(f, " !important")?;
+    }
+    write!(f, ";")
   }
+}
 
-  fn parse_unit(&mut self) -> Unit {
-    match &*self.parse_identifier().to_ascii_lowercase() {
-      "px" => Unit::Px,
-      _ => panic!("Unrecognized unit"),
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Keyword(keyword) => write!(f, "{}", keyword),
+      Value::String(string) => write!(f, "{}", quote(string)),
+      Value::Number(number) => write!(f, "{}", number),
+      Value::Length(length, unit) => write!(f, "{}{}", length, unit),
+      Value::ColorValue(color) => write!(f, "{}", color),
+      Value::Url(url) => write!(f, "url({})", quote(url)),
+      Value::Format(format) => write!(f, "format({})", quote(format)),
+      Value::List(values) => write_separated(f, values, " "),
+      Value::CommaList(values) => write_separated(f, values, ", "),
+      Value::Variable(name) => write!(f, "var({})", name),
+      Value::VariableWithFallback(name, fallback) => write!(f, "var({}, {})", name, fallback),
+      Value::Calc(expr) => write!(f, "calc({})", expr),
+      Value::BorderStyle(style) => write!(f, "{}", style),
     }
   }
+}
 
-  fn parse_color(&mut self) -> Value {
-    assert_eq!(self.consume_char(), '#');
-    Value::ColorValue(Color {
-      r: self.parse_hex_pair(),
-      g: self.parse_hex_pair(),
-      b: self.parse_hex_pair(),
-      a: 255,
-    })
+impl fmt::Display for CalcExpr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    // Operands that bind less tightly than their operator are put in parentheses,
+    // and so are right operands of the same precedence, since `-` and `/` aren't
+    // associative.
+    let (a, op, b, precedence) = match self {
+      CalcExpr::Number(number) => return write!(f, "{}", number),
+      CalcExpr::Length(length, unit) => return write!(f, "{}{}", length, unit),
+      CalcExpr::Percentage(percentage) => return write!(f, "{}%", percentage),
+      CalcExpr::Add(a, b) => (a, "+", b, 1),
+      CalcExpr::Sub(a, b) => (a, "-", b, 1),
+      CalcExpr::Mul(a, b) => (a, "*", b, 2),
+      CalcExpr::Div(a, b) => (a, "/", b, 2),
+    };
+    if a.precedence() < precedence {
+      write!(f, "({})", a)?;
+    } else {
+      write!(f, "{}", a)?;
+    }
+    write!(f, " {} ", op)?;
+    if b.precedence() <= precedence {
+      write!(f, "({})", b)
+    } else {
+      write!(f, "{}", b)
+    }
   }
+}
 
-  // Parse two hexadecimal digits.
-  fn parse_hex_pair(&mut self) -> u8 {
-    let s = &self.input[self.position..self.position + 2];
-    self.position += 2;
-    u8::from_str_radix(s, 16).unwrap()
+impl CalcExpr {
+  // How tightly the expression's operator binds, for deciding where parentheses go.
+  fn precedence(&self) -> u8 {
+    match self {
+      CalcExpr::Add(..) | CalcExpr::Sub(..) => 1,
+      CalcExpr::Mul(..) | CalcExpr::Div(..) => 2,
+      CalcExpr::Number(_) | CalcExpr::Length(..) | CalcExpr::Percentage(_) => 3,
+    }
   }
+}
 
-  // Parse a propety name or keyword.
-  fn parse_identifier(&mut self) -> String {
-    self.consume_while(valid_identifier_char)
+impl fmt::Display for BorderStyle {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      BorderStyle::None => "none",
+      BorderStyle::Hidden => "hidden",
+      BorderStyle::Dotted => "dotted",
+      BorderStyle::Dashed => "dashed",
+      BorderStyle::Solid => "solid",
+      BorderStyle::Double => "double",
+      BorderStyle::Groove => "groove",
+      BorderStyle::Ridge => "ridge",
+      BorderStyle::Inset => "inset",
+      BorderStyle::Outset => "outset",
+    };
+    write!(f, "{}", name)
   }
+}
 
-  // Parse one simple selector, e.g.: `type#id.class1.class2.class3`
-  fn parse_simple_selector(&mut self) -> SimpleSelector {
-    let mut selector = SimpleSelector {
-      tag_name: None,
-      id: None,
-      class: Vec::new(),
+impl fmt::Display for Unit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Unit::Px => "px",
+      Unit::Percent => "%",
+      Unit::Em => "em",
+      Unit::Rem => "rem",
+      Unit::Ex => "ex",
+      Unit::Ch => "ch",
+      Unit::Vh => "vh",
+      Unit::Vw => "vw",
+      Unit::Pt => "pt",
+      Unit::Pc => "pc",
+      Unit::Cm => "cm",
+      Unit::Mm => "mm",
+      Unit::In => "in",
+      Unit::Q => "q",
     };
+    write!(f, "{}", name)
+  }
+}
 
-    while !self.eof() {
-      match self.next_char() {
-        '#' => {
-          self.consume_char();
-          selector.id = Some(self.parse_identifier());
-        }
-        '.' => {
-          self.consume_char();
-          selector.class.push(self.parse_identifier());
-        }
-        '*' => {
-          self.consume_char();
-        }
-        c if valid_identifier_char(c) => {
-          selector.tag_name = Some(self.parse_identifier());
-        }
-        _ => break,
+impl fmt::Display for Color {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.to_hex())
+  }
+}
+
+// Write `items` with `separator` between them.
+fn write_separated<T: fmt::Display>(
+  f: &mut fmt::Formatter,
+  items: &[T],
+  separator: &str,
+) -> fmt::Result {
+  for (i, item) in items.iter().enumerate() {
+    if i > 0 {
+      write!(f, "{}", separator)?;
+    }
+    write!(f, "{}", item)?;
+  }
+  Ok(())
+}
+
+// Write ` { <declarations> }`, with a space before each declaration.
+fn write_declaration_block(f: &mut fmt::Formatter, declarations: &[Declaration]) -> fmt::Result {
+  write!(f, " {{")?;
+  for declaration in declarations {
+    write!(f, " {}", declaration)?;
+  }
+  write!(f, " }}")
+}
+
+// Quote a string, using single quotes if it contains a double quote but no single quote.
+// Otherwise the characters that can't appear as they are in the string are escaped.
+fn quote(s: &str) -> String {
+  let quote = if s.contains('"') && !s.contains('\'') { '\'' } else { '"' };
+  let mut quoted = quote.to_string();
+  for c in s.chars() {
+    match c {
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\a "),
+      c if c == quote => {
+        quoted.push('\\');
+        quoted.push(c);
       }
+      c => quoted.push(c),
     }
-    return selector;
   }
+  quoted.push(quote);
+  quoted
 }
 
-fn valid_identifier_char(c: char) -> bool {
-  match c {
-    'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
-    _ => false,
+struct Parser {
+  tokens: Vec<(usize, CssToken)>, // Each token with the byte offset it starts at.
+  index: usize,                   // The next token. The last one is always `Eof`.
+  input: String,
+  // The error in a string, comment or URL cut off by the end of the input, which takes
+  // the place of `UnexpectedEof` once the parser gets there.
+  end_error: Option<CssError>,
+  errors: Vec<ParseError>, // Errors that were recovered from.
+}
+
+impl Parser {
+  fn new(input: String) -> Parser {
+    let mut tokens = Vec::new();
+    let mut end_error = None;
+    let mut tokenizer = Tokenizer::new(&input);
+    loop {
+      let position = tokenizer.position();
+      let token = tokenizer.next_token();
+      // The unterminated token is left out, so that the error is reported where it starts.
+      if let Some((position, error)) = tokenizer.take_error() {
+        tokens.push((position, CssToken::Eof));
+        end_error = Some(error);
+        break;
+      }
+      let eof = token == CssToken::Eof;
+      tokens.push((position, token));
+      if eof {
+        break;
+      }
+    }
+    Parser {
+      tokens,
+      index: 0,
+      input,
+      end_error,
+      errors: Vec::new(),
+    }
+  }
+
+  // The byte offset of the next token.
+  fn position(&self) -> usize {
+    self.tokens[self.index].0
+  }
+
+  // Read the next token without consuming it.
+  fn next_token(&self) -> &CssToken {
+    &self.tokens[self.index].1
+  }
+
+  // Read the next token without consuming it, failing at the end of the input.
+  fn peek_token(&self) -> Result<&CssToken, CssError> {
+    match self.next_token() {
+      CssToken::Eof => Err(self.end_error.clone().unwrap_or(CssError::UnexpectedEof)),
+      token => Ok(token),
+    }
+  }
+
+  // The source text of the next token.
+  fn token_text(&self) -> &str {
+    let end = match self.tokens.get(self.index + 1) {
+      Some(&(end, _)) => end,
+      None => self.input.len(),
+    };
+    &self.input[self.position()..end]
+  }
+
+  // Is the next token `token`?
+  fn next_is(&self, token: &CssToken) -> bool {
+    self.next_token() == token
+  }
+
+  // Return true if all input is consumed.
+  fn eof(&self) -> bool {
+    self.next_is(&CssToken::Eof)
+  }
+
+  // Return the next token and advance past it. `Eof` is never consumed.
+  fn consume_token(&mut self) -> CssToken {
+    let token = self.next_token().clone();
+    if token != CssToken::Eof {
+      self.index += 1;
+    }
+    token
+  }
+
+  // Consume the next token, failing unless it is `expected`.
+  // An unexpected token is left in place, so errors point at it.
+  fn expect_token(&mut self, expected: &CssToken) -> Result<(), CssError> {
+    if self.peek_token()? != expected {
+      return Err(self.unexpected());
+    }
+    self.consume_token();
+    Ok(())
+  }
+
+  // The error for a next token that can't go where it is, reported as its first character.
+  fn unexpected(&self) -> CssError {
+    match self.peek_token() {
+      Ok(_) => match self.token_text().chars().next() {
+        Some(c) => CssError::UnexpectedChar(c),
+        None => CssError::UnexpectedEof,
+      },
+      Err(error) => error,
+    }
+  }
+
+  // Remember an error at the current position, to carry on parsing after it.
+  fn record_error(&mut self, error: CssError) {
+    let error = self.locate_error(error);
+    self.errors.push(error);
+  }
+
+  // Attach the current position to an error.
+  fn locate_error(&self, error: CssError) -> ParseError {
+    let position = self.position();
+    let (line, column) = location::line_column(&self.input, position);
+    ParseError {
+      error,
+      position,
+      line,
+      column,
+    }
+  }
+
+  // Consume and discard any whitespace and comments.
+  fn skip_irrelevant(&mut self) -> Result<(), CssError> {
+    while matches!(self.next_token(), CssToken::WhiteSpace | CssToken::Comment(_)) {
+      self.index += 1;
+    }
+    match self.end_error {
+      Some(ref error) if self.eof() => Err(error.clone()),
+      _ => Ok(()),
+    }
+  }
+
+  // Parse a list of rule sets and at-rules, separated by optional whitespace.
+  // An invalid rule is recorded in `errors` and skipped.
+  fn parse_items(&mut self) -> Vec<CssItem> {
+    let mut items = Vec::new();
+    loop {
+      if let Err(error) = self.skip_irrelevant() {
+        self.record_error(error);
+        break;
+      }
+      let start = self.index;
+      let item = match self.next_token() {
+        CssToken::Eof => break,
+        CssToken::AtKeyword(_) | CssToken::Delim('@') => self.parse_at_rule().map(CssItem::AtRule),
+        _ => self.parse_rule().map(CssItem::Rule),
+      };
+      match item {
+        // Imports after other rules are ignored.
+        Ok(CssItem::AtRule(AtRule::Import(_)))
+          if items
+            .iter()
+            .any(|item| !matches!(item, CssItem::AtRule(AtRule::Import(_)))) =>
+        {
+          let end = self.index;
+          self.index = start;
+          self.record_error(CssError::MisplacedImport);
+          self.index = end;
+        }
+        Ok(item) => items.push(item),
+        Err(error) => {
+          self.record_error(error);
+          // Nothing follows a rule cut off by the end of the input.
+          if self.eof() {
+            break;
+          }
+          self.skip_rule();
+          // A stray `}` can't end anything at the top level, so drop it.
+          if self.index == start {
+            self.consume_token();
+          }
+        }
+      }
+    }
+    items
+  }
+
+  // Parse a list of rule sets enclosed in `{ ... }`.
+  fn parse_rule_block(&mut self) -> Result<Vec<Rule>, CssError> {
+    self.expect_token(&CssToken::BraceOpen)?;
+    let mut rules = Vec::new();
+    loop {
+      self.skip_irrelevant()?;
+      if self.peek_token()? == &CssToken::BraceClose {
+        self.consume_token();
+        break;
+      }
+      match self.parse_rule() {
+        Ok(rule) => rules.push(rule),
+        Err(error) if !self.eof() => {
+          self.record_error(error);
+          self.skip_rule();
+        }
+        Err(error) => return Err(error),
+      }
+    }
+    Ok(rules)
+  }
+
+  // Parse an at-rule, e.g.: `@media screen { ... }`
+  fn parse_at_rule(&mut self) -> Result<AtRule, CssError> {
+    // A `@` that isn't followed by a name is an at-rule with an empty one.
+    let name = match self.consume_token() {
+      CssToken::AtKeyword(name) => name,
+      _ => String::new(),
+    };
+    match &*name.to_ascii_lowercase() {
+      "import" => Ok(AtRule::Import(self.parse_import_rule()?)),
+      "media" => Ok(AtRule::Media(MediaRule {
+        queries: self.parse_media_query_list()?,
+        rules: self.parse_rule_block()?,
+      })),
+      "keyframes" => Ok(AtRule::Keyframes(self.parse_keyframes_rule()?)),
+      "font-face" => {
+        self.skip_irrelevant()?;
+        Ok(AtRule::FontFace(FontFaceRule {
+          declarations: self.parse_declarations()?,
+        }))
+      }
+      _ => Err(CssError::UnknownAtRule(name)),
+    }
+  }
+
+  // Parse the rest of an import rule after `@import`: `url(<url>) [<queries>];`
+  fn parse_import_rule(&mut self) -> Result<ImportRule, CssError> {
+    self.skip_irrelevant()?;
+    let url = match self.peek_token()?.clone() {
+      CssToken::String(url) | CssToken::Url(url) => {
+        self.consume_token();
+        url
+      }
+      CssToken::BadUrl => return Err(CssError::InvalidArgument("url".to_string())),
+      _ => {
+        self.expect_keyword("url")?;
+        self.parse_url()?
+      }
+    };
+    self.skip_irrelevant()?;
+    let queries = if self.next_is(&CssToken::Semicolon) {
+      Vec::new()
+    } else {
+      self.parse_media_query_list()?
+    };
+    self.expect_token(&CssToken::Semicolon)?;
+    Ok(ImportRule { url, queries })
+  }
+
+  // Parse the rest of a keyframes rule after `@keyframes`: `<name> { <keyframes> }`
+  fn parse_keyframes_rule(&mut self) -> Result<KeyframesRule, CssError> {
+    self.skip_irrelevant()?;
+    let name = self.parse_identifier();
+    if name.is_empty() {
+      return Err(CssError::InvalidKeyframes(name));
+    }
+    self.skip_irrelevant()?;
+    self.expect_token(&CssToken::BraceOpen)?;
+    let mut keyframes = Vec::new();
+    loop {
+      self.skip_irrelevant()?;
+      if self.peek_token()? == &CssToken::BraceClose {
+        self.consume_token();
+        break;
+      }
+      let selectors = self.parse_keyframe_selectors()?;
+      let mut declarations = self.parse_declarations()?;
+      // `!important` declarations are ignored inside keyframes.
+      // See: https://www.w3.org/TR/css-animations-1/#keyframes
+      declarations.retain(|declaration| !declaration.important);
+      keyframes.push(Keyframe {
+        selectors,
+        declarations,
+      });
+    }
+    Ok(KeyframesRule { name, keyframes })
+  }
+
+  // Parse a comma-separated list of `from`, `to` or percentages.
+  fn parse_keyframe_selectors(&mut self) -> Result<Vec<KeyframeSelector>, CssError> {
+    let mut selectors = Vec::new();
+    loop {
+      self.skip_irrelevant()?;
+      let selector = match *self.peek_token()? {
+        CssToken::Percentage(percentage) => {
+          self.consume_token();
+          KeyframeSelector::Percentage(percentage)
+        }
+        _ => {
+          let keyword = self.parse_identifier();
+          match &*keyword.to_ascii_lowercase() {
+            "from" => KeyframeSelector::From,
+            "to" => KeyframeSelector::To,
+            _ => return Err(CssError::InvalidKeyframes(keyword)),
+          }
+        }
+      };
+      selectors.push(selector);
+      self.skip_irrelevant()?;
+      if self.next_is(&CssToken::Comma) {
+        self.consume_token();
+      } else {
+        return Ok(selectors);
+      }
+    }
+  }
+
+  // Parse a comma-separated list of media queries, up to the opening `{`.
+  fn parse_media_query_list(&mut self) -> Result<Vec<MediaQuery>, CssError> {
+    let mut queries = Vec::new();
+    loop {
+      queries.push(self.parse_media_query()?);
+      if self.next_is(&CssToken::Comma) {
+        self.consume_token();
+      } else {
+        return Ok(queries);
+      }
+    }
+  }
+
+  // Parse a media query: `[<media type>] [and (<feature>[: <value>])]*`
+  fn parse_media_query(&mut self) -> Result<MediaQuery, CssError> {
+    self.skip_irrelevant()?;
+    let mut query = MediaQuery {
+      media_type: None,
+      conditions: Vec::new(),
+    };
+    if !self.next_is(&CssToken::ParenOpen) {
+      let error = self.unexpected();
+      let media_type = self.parse_identifier();
+      if media_type.is_empty() {
+        return Err(error);
+      }
+      query.media_type = Some(media_type.to_ascii_lowercase());
+      self.skip_irrelevant()?;
+      if self.ends_media_query() {
+        return Ok(query);
+      }
+      self.expect_keyword("and")?;
+    }
+    loop {
+      self.skip_irrelevant()?;
+      query.conditions.push(self.parse_media_condition()?);
+      self.skip_irrelevant()?;
+      if self.ends_media_query() {
+        return Ok(query);
+      }
+      self.expect_keyword("and")?;
+    }
+  }
+
+  // Is the next token the `{`, `,` or `;` after a media query?
+  fn ends_media_query(&self) -> bool {
+    matches!(
+      self.next_token(),
+      CssToken::BraceOpen | CssToken::Comma | CssToken::Semicolon
+    )
+  }
+
+  // Parse one media feature test: `(<feature>[: <value>])`
+  fn parse_media_condition(&mut self) -> Result<MediaCondition, CssError> {
+    self.expect_token(&CssToken::ParenOpen)?;
+    self.skip_irrelevant()?;
+    let feature = self.parse_identifier().to_ascii_lowercase();
+    self.skip_irrelevant()?;
+    let mut value = None;
+    if self.next_is(&CssToken::Colon) {
+      self.consume_token();
+      self.skip_irrelevant()?;
+      value = Some(self.parse_value()?);
+      self.skip_irrelevant()?;
+    }
+    self.expect_token(&CssToken::ParenClose)?;
+    Ok(MediaCondition { feature, value })
+  }
+
+  // Consume the given keyword, ignoring case.
+  fn expect_keyword(&mut self, keyword: &str) -> Result<(), CssError> {
+    let error = self.unexpected();
+    if !self.parse_identifier().eq_ignore_ascii_case(keyword) {
+      return Err(error);
+    }
+    Ok(())
+  }
+
+  // Parse a rule set: `<selectors> { <declaarations> }`
+  fn parse_rule(&mut self) -> Result<Rule, CssError> {
+    Ok(Rule {
+      selectors: self.parse_selectors()?,
+      declarations: self.parse_declarations()?,
+    })
+  }
+
+  // Parse a comma-separated list of selectors.
+  fn parse_selectors(&mut self) -> Result<Vec<Selector>, CssError> {
+    let mut selectors = Vec::new();
+    loop {
+      selectors.push(self.parse_selector()?);
+
+      match self.peek_token()? {
+        CssToken::Comma => {
+          self.consume_token();
+          self.skip_irrelevant()?;
+        }
+        CssToken::BraceOpen => break,
+        _ => return Err(self.unexpected()),
+      }
+    }
+    // Return selectors with highest specifity first, for use in matching.
+    selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+    Ok(selectors)
+  }
+
+  // Parse a comma-separated list of selectors that makes up the whole input.
+  fn parse_selector_list(&mut self) -> Result<Vec<Selector>, CssError> {
+    let mut selectors = Vec::new();
+    self.skip_irrelevant()?;
+    loop {
+      let start = self.index;
+      selectors.push(self.parse_selector()?);
+      if self.index == start {
+        return Err(self.unexpected());
+      }
+      if self.eof() {
+        return Ok(selectors);
+      }
+      self.expect_token(&CssToken::Comma)?;
+      self.skip_irrelevant()?;
+    }
+  }
+
+  // Parse one selector, which may be a chain of simple selectors joined by combinators.
+  // Stops before the `,` or `{` that ends the selector, or at the end of the input.
+  fn parse_selector(&mut self) -> Result<Selector, CssError> {
+    let first = self.parse_simple_selector()?;
+    let mut rest = Vec::new();
+    loop {
+      let start = self.index;
+      self.skip_irrelevant()?;
+      if self.eof() {
+        break;
+      }
+      let combinator = match *self.peek_token()? {
+        CssToken::Comma | CssToken::BraceOpen => break,
+        CssToken::Delim(c @ ('>' | '+' | '~')) => {
+          self.consume_token();
+          self.skip_irrelevant()?;
+          match c {
+            '>' => Combinator::Child,
+            '+' => Combinator::AdjacentSibling,
+            _ => Combinator::GeneralSibling,
+          }
+        }
+        _ if self.index > start => Combinator::Descendant,
+        _ => return Err(self.unexpected()),
+      };
+      let start = self.index;
+      let simple = self.parse_simple_selector()?;
+      if self.index == start {
+        return Err(self.unexpected());
+      }
+      rest.push((combinator, simple));
+    }
+
+    if rest.is_empty() {
+      Ok(Selector::Simple(first))
+    } else {
+      Ok(Selector::Complex(ComplexSelector { first, rest }))
+    }
+  }
+
+  // Parse a list of declarations enclosed in `{ ... }`.
+  fn parse_declarations(&mut self) -> Result<Vec<Declaration>, CssError> {
+    self.expect_token(&CssToken::BraceOpen)?;
+    let mut declarations = Vec::new();
+    loop {
+      self.skip_irrelevant()?;
+      if self.peek_token()? == &CssToken::BraceClose {
+        self.consume_token();
+        break;
+      }
+      match self.parse_declaration() {
+        Ok(expanded) => declarations.extend(expanded),
+        Err(error) if !self.eof() => {
+          self.record_error(error);
+          self.skip_declaration();
+        }
+        Err(error) => return Err(error),
+      }
+    }
+    Ok(declarations)
+  }
+
+  // Skip the rest of an invalid declaration, up to and including the next `;`,
+  // or up to the `}` that closes the block.
+  fn skip_declaration(&mut self) {
+    self.skip_until(|token| matches!(token, CssToken::Semicolon | CssToken::BraceClose));
+    if self.next_is(&CssToken::Semicolon) {
+      self.consume_token();
+    }
+  }
+
+  // Skip the rest of an invalid rule, up to and including its `{ ... }` block or a `;`
+  // ending an at-rule like `@import`, or up to the `}` that closes the enclosing block.
+  fn skip_rule(&mut self) {
+    self.skip_until(|token| {
+      matches!(
+        token,
+        CssToken::BraceOpen | CssToken::BraceClose | CssToken::Semicolon
+      )
+    });
+    if self.next_is(&CssToken::Semicolon) {
+      self.consume_token();
+    } else if self.next_is(&CssToken::BraceOpen) {
+      self.consume_token();
+      self.skip_until(|token| *token == CssToken::BraceClose);
+      self.consume_token();
+    }
+  }
+
+  // Consume tokens until `test` matches one outside of any brackets, without consuming
+  // it. Stops at the end of the input.
+  fn skip_until<F>(&mut self, test: F)
+  where
+    F: Fn(&CssToken) -> bool,
+  {
+    let mut closing = Vec::new();
+    while !self.eof() {
+      if closing.is_empty() && test(self.next_token()) {
+        return;
+      }
+      match self.consume_token() {
+        CssToken::ParenOpen => closing.push(CssToken::ParenClose),
+        CssToken::BracketOpen => closing.push(CssToken::BracketClose),
+        CssToken::BraceOpen => closing.push(CssToken::BraceClose),
+        token if closing.last() == Some(&token) => {
+          closing.pop();
+        }
+        _ => {}
+      }
+    }
+  }
+
+  // Parse one `<property>: <value> [!important];` declaration.
+  // A shorthand property is expanded into its longhands.
+  fn parse_declaration(&mut self) -> Result<Vec<Declaration>, CssError> {
+    let property_name = self.parse_identifier();
+    self.skip_irrelevant()?;
+    self.expect_token(&CssToken::Colon)?;
+    self.skip_irrelevant()?;
+    let value = self.parse_value_list()?;
+    let important = self.parse_important()?;
+    let declarations = expand_shorthand(Declaration {
+      name: property_name,
+      value,
+      important,
+    })?;
+    // The last declaration in a block doesn't need a `;`.
+    if !self.next_is(&CssToken::BraceClose) {
+      self.expect_token(&CssToken::Semicolon)?;
+    }
+    Ok(declarations)
+  }
+
+  // Parse an optional `!important` annotation.
+  fn parse_important(&mut self) -> Result<bool, CssError> {
+    if !self.next_is(&CssToken::Delim('!')) {
+      return Ok(false);
+    }
+    self.consume_token();
+    self.skip_irrelevant()?;
+    let keyword = self.parse_identifier();
+    if !keyword.eq_ignore_ascii_case("important") {
+      return Err(CssError::InvalidAnnotation(keyword));
+    }
+    self.skip_irrelevant()?;
+    Ok(true)
+  }
+
+  // Parse the value of a declaration, which may be a list of space-separated values
+  // or of comma-separated groups of them. A single value is returned as it is.
+  fn parse_value_list(&mut self) -> Result<Value, CssError> {
+    let mut groups = Vec::new();
+    loop {
+      let mut values = Vec::new();
+      loop {
+        values.push(self.parse_value()?);
+        self.skip_irrelevant()?;
+        if matches!(
+          self.next_token(),
+          CssToken::Eof
+            | CssToken::Semicolon
+            | CssToken::Delim('!')
+            | CssToken::BraceClose
+            | CssToken::Comma
+            | CssToken::ParenClose
+        ) {
+          break;
+        }
+      }
+      groups.push(match values.len() {
+        1 => values.remove(0),
+        _ => Value::List(values),
+      });
+      if !self.next_is(&CssToken::Comma) {
+        break;
+      }
+      self.consume_token();
+      self.skip_irrelevant()?;
+    }
+    Ok(match groups.len() {
+      1 => groups.remove(0),
+      _ => Value::CommaList(groups),
+    })
+  }
+
+  // Methods for parsing values.
+  fn parse_value(&mut self) -> Result<Value, CssError> {
+    let value = match self.peek_token()?.clone() {
+      CssToken::Number(number) => Value::Number(number),
+      CssToken::Percentage(number) => Value::Length(number, Unit::Percent),
+      CssToken::Dimension(number, unit) => Value::Length(number, parse_unit(&unit)?),
+      CssToken::Hash(digits) => parse_hex_color(digits)?,
+      CssToken::String(string) => Value::String(string),
+      CssToken::Url(url) => Value::Url(url),
+      CssToken::BadUrl => return Err(CssError::InvalidArgument("url".to_string())),
+      CssToken::Ident(name) => {
+        self.consume_token();
+        return self.parse_keyword(name);
+      }
+      _ => return Err(self.unexpected()),
+    };
+    self.consume_token();
+    Ok(value)
+  }
+
+  // Parse the rest of a value that starts with the identifier `name`: a function, or a
+  // named color, a border style or another keyword.
+  fn parse_keyword(&mut self, name: String) -> Result<Value, CssError> {
+    if self.next_is(&CssToken::ParenOpen) {
+      self.parse_function(name)
+    } else if let Some(color) = named_color(&name) {
+      Ok(Value::ColorValue(color))
+    } else if let Some(style) = border_style(&name).filter(|style| {
+      // `none` and `hidden` mean other things for `display` and `visibility`,
+      // so they are only read as border styles by `expand_border_shorthand`.
+      !matches!(style, BorderStyle::None | BorderStyle::Hidden)
+    }) {
+      Ok(Value::BorderStyle(style))
+    } else {
+      Ok(Value::Keyword(name))
+    }
+  }
+
+  // Parse a functional value such as `rgb(255, 0, 128)`.
+  fn parse_function(&mut self, name: String) -> Result<Value, CssError> {
+    match &*name.to_ascii_lowercase() {
+      "rgb" | "rgba" => self.parse_rgb_color(name),
+      "hsl" | "hsla" => self.parse_hsl_color(name),
+      "url" => Ok(Value::Url(self.parse_url()?)),
+      "var" => self.parse_variable(),
+      "calc" => Ok(Value::Calc(self.parse_calc_arguments()?)),
+      "format" => match &self.parse_arguments()?[..] {
+        [Value::String(format)] => Ok(Value::Format(format.clone())),
+        _ => Err(CssError::InvalidArgument(name)),
+      },
+      _ => Err(CssError::UnknownFunction(name)),
+    }
+  }
+
+  // Parse the parenthesized part of `calc(...)`, or a parenthesized group inside it.
+  fn parse_calc_arguments(&mut self) -> Result<CalcExpr, CssError> {
+    self.expect_token(&CssToken::ParenOpen)?;
+    self.skip_irrelevant()?;
+    let expr = self.parse_calc_expr()?;
+    self.skip_irrelevant()?;
+    self.expect_token(&CssToken::ParenClose)?;
+    Ok(expr)
+  }
+
+  // Parse a sum: `<product> [[+ | -] <product>]*`
+  // `+` and `-` must be surrounded by whitespace, so that `1px -2px` isn't a subtraction.
+  fn parse_calc_expr(&mut self) -> Result<CalcExpr, CssError> {
+    let mut expr = self.parse_calc_product()?;
+    loop {
+      let start = self.index;
+      self.skip_irrelevant()?;
+      let operator = match *self.next_token() {
+        CssToken::Delim(c @ ('+' | '-')) if self.index > start => c,
+        _ => return Ok(expr),
+      };
+      self.consume_token();
+      if !self.next_is(&CssToken::WhiteSpace) {
+        return Err(CssError::UnexpectedChar(operator));
+      }
+      self.skip_irrelevant()?;
+      let rhs = Box::new(self.parse_calc_product()?);
+      expr = match operator {
+        '+' => CalcExpr::Add(Box::new(expr), rhs),
+        _ => CalcExpr::Sub(Box::new(expr), rhs),
+      };
+    }
+  }
+
+  // Parse a product: `<term> [[* | /] <term>]*`
+  fn parse_calc_product(&mut self) -> Result<CalcExpr, CssError> {
+    let mut expr = self.parse_calc_term()?;
+    loop {
+      let start = self.index;
+      self.skip_irrelevant()?;
+      let operator = match *self.next_token() {
+        CssToken::Delim(c @ ('*' | '/')) => c,
+        _ => {
+          // Leave the whitespace for `parse_calc_expr` to check.
+          self.index = start;
+          return Ok(expr);
+        }
+      };
+      self.consume_token();
+      self.skip_irrelevant()?;
+      let rhs = Box::new(self.parse_calc_term()?);
+      expr = match operator {
+        '*' => CalcExpr::Mul(Box::new(expr), rhs),
+        _ => CalcExpr::Div(Box::new(expr), rhs),
+      };
+    }
+  }
+
+  // Parse a number, a length, a percentage, or a parenthesized expression.
+  fn parse_calc_term(&mut self) -> Result<CalcExpr, CssError> {
+    let error = self.unexpected();
+    let term = match self.peek_token()?.clone() {
+      CssToken::ParenOpen => return self.parse_calc_arguments(),
+      // A nested `calc()` is the same as a parenthesized expression.
+      CssToken::Ident(name) if name.eq_ignore_ascii_case("calc") => {
+        self.consume_token();
+        if !self.next_is(&CssToken::ParenOpen) {
+          return Err(error);
+        }
+        return self.parse_calc_arguments();
+      }
+      CssToken::Number(number) => CalcExpr::Number(number),
+      CssToken::Percentage(number) => CalcExpr::Percentage(number),
+      CssToken::Dimension(number, unit) => CalcExpr::Length(number, parse_unit(&unit)?),
+      _ => return Err(error),
+    };
+    self.consume_token();
+    Ok(term)
+  }
+
+  // Parse the parenthesized part of `var(--name)` or `var(--name, fallback)`.
+  fn parse_variable(&mut self) -> Result<Value, CssError> {
+    self.expect_token(&CssToken::ParenOpen)?;
+    self.skip_irrelevant()?;
+    let name = self.parse_identifier();
+    if !name.starts_with("--") {
+      return Err(CssError::InvalidArgument("var".to_string()));
+    }
+    self.skip_irrelevant()?;
+    if self.next_is(&CssToken::ParenClose) {
+      self.consume_token();
+      return Ok(Value::Variable(name));
+    }
+    self.expect_token(&CssToken::Comma)?;
+    self.skip_irrelevant()?;
+    let fallback = self.parse_value_list()?;
+    self.expect_token(&CssToken::ParenClose)?;
+    Ok(Value::VariableWithFallback(name, Box::new(fallback)))
+  }
+
+  // Parse the parenthesized part of a quoted `url("...")`. An unquoted URL is a single
+  // `Url` token instead.
+  fn parse_url(&mut self) -> Result<String, CssError> {
+    self.expect_token(&CssToken::ParenOpen)?;
+    self.skip_irrelevant()?;
+    let url = match self.peek_token()?.clone() {
+      CssToken::String(url) => url,
+      _ => return Err(self.unexpected()),
+    };
+    self.consume_token();
+    self.skip_irrelevant()?;
+    self.expect_token(&CssToken::ParenClose)?;
+    Ok(url)
+  }
+
+  // Parse the arguments of `rgb(r, g, b)` or `rgba(r, g, b, a)`.
+  // Each channel is either a number from 0 to 255 or a percentage.
+  // The alpha channel is given as 0.0-1.0 or a percentage and scaled into 0-255.
+  fn parse_rgb_color(&mut self, name: String) -> Result<Value, CssError> {
+    let args = self.parse_arguments()?;
+    let (r, g, b, alpha) = match &args[..] {
+      [r, g, b] => (r, g, b, None),
+      [r, g, b, a] => (r, g, b, Some(a)),
+      _ => return Err(CssError::WrongArgumentCount(name, args.len())),
+    };
+    Ok(Value::ColorValue(Color {
+      r: color_channel(r, &name)?,
+      g: color_channel(g, &name)?,
+      b: color_channel(b, &name)?,
+      a: match alpha {
+        Some(alpha) => alpha_channel(alpha, &name)?,
+        None => 255,
+      },
+    }))
+  }
+
+  // Parse the arguments of `hsl(h, s%, l%)` or `hsla(h, s%, l%, a)`.
+  // The hue is in degrees and wraps around; saturation and lightness are clamped to 0-100%.
+  fn parse_hsl_color(&mut self, name: String) -> Result<Value, CssError> {
+    let args = self.parse_arguments()?;
+    let (hue, saturation, lightness, alpha) = match &args[..] {
+      [h, s, l] => (h, s, l, None),
+      [h, s, l, a] => (h, s, l, Some(a)),
+      _ => return Err(CssError::WrongArgumentCount(name, args.len())),
+    };
+    let hue = match *hue {
+      Value::Number(h) => h.rem_euclid(360.0),
+      _ => return Err(CssError::InvalidArgument(name)),
+    };
+    let (saturation, lightness) = match (saturation, lightness) {
+      (&Value::Length(s, Unit::Percent), &Value::Length(l, Unit::Percent)) => {
+        (s.clamp(0.0, 100.0) / 100.0, l.clamp(0.0, 100.0) / 100.0)
+      }
+      _ => return Err(CssError::InvalidArgument(name)),
+    };
+    let (r, g, b) = hsl_to_rgb(hue, saturation, lightness);
+    Ok(Value::ColorValue(Color {
+      r,
+      g,
+      b,
+      a: match alpha {
+        Some(alpha) => alpha_channel(alpha, &name)?,
+        None => 255,
+      },
+    }))
+  }
+
+  // Parse a parenthesized, comma-separated list of values: `( v, v, ... )`.
+  fn parse_arguments(&mut self) -> Result<Vec<Value>, CssError> {
+    self.expect_token(&CssToken::ParenOpen)?;
+    let mut args = Vec::new();
+    loop {
+      self.skip_irrelevant()?;
+      args.push(self.parse_value()?);
+      self.skip_irrelevant()?;
+      match self.peek_token()? {
+        CssToken::Comma => {}
+        CssToken::ParenClose => {
+          self.consume_token();
+          break;
+        }
+        _ => return Err(self.unexpected()),
+      }
+      self.consume_token();
+    }
+    Ok(args)
+  }
+
+  // Parse a propety name or keyword. Returns an empty string if the next token isn't one.
+  fn parse_identifier(&mut self) -> String {
+    match *self.next_token() {
+      CssToken::Ident(ref name) => {
+        let name = name.clone();
+        self.consume_token();
+        name
+      }
+      _ => String::new(),
+    }
+  }
+
+  // Parse one simple selector, e.g.: `type#id.class1.class2.class3[attr=value]`
+  fn parse_simple_selector(&mut self) -> Result<SimpleSelector, CssError> {
+    let mut selector = SimpleSelector {
+      tag_name: None,
+      id: None,
+      class: Vec::new(),
+      attributes: Vec::new(),
+      pseudo_classes: Vec::new(),
+      pseudo_element: None,
+    };
+
+    loop {
+      match self.next_token().clone() {
+        CssToken::Hash(id) => {
+          self.consume_token();
+          selector.id = Some(id);
+        }
+        CssToken::Delim('.') => {
+          self.consume_token();
+          selector.class.push(self.parse_identifier());
+        }
+        CssToken::Delim('*') => {
+          self.consume_token();
+        }
+        CssToken::BracketOpen => selector.attributes.push(self.parse_attribute_selector()?),
+        CssToken::Colon => {
+          self.consume_token();
+          let double_colon = self.next_is(&CssToken::Colon);
+          if double_colon {
+            self.consume_token();
+          }
+          let name = self.parse_identifier();
+          // `:before`, `:after`, `:first-line` and `:first-letter` are legacy pseudo-elements.
+          let pseudo_element = match pseudo_element(&name) {
+            Some(element) if double_colon || element.allows_single_colon() => element,
+            _ if double_colon => return Err(CssError::UnexpectedPseudoElement(name)),
+            _ => {
+              selector.pseudo_classes.push(self.parse_pseudo_class(name)?);
+              continue;
+            }
+          };
+          // A selector can only have one pseudo-element.
+          if selector.pseudo_element.is_some() {
+            return Err(CssError::UnexpectedPseudoElement(name));
+          }
+          selector.pseudo_element = Some(pseudo_element);
+        }
+        CssToken::Ident(name) => {
+          self.consume_token();
+          selector.tag_name = Some(name);
+        }
+        _ => break,
+      }
+    }
+    Ok(selector)
+  }
+
+  // Parse an attribute selector, e.g.: `[attr]`, `[attr=value]` or `[attr~="value"]`
+  fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, CssError> {
+    self.expect_token(&CssToken::BracketOpen)?;
+    self.skip_irrelevant()?;
+    let name = self.parse_identifier();
+    self.skip_irrelevant()?;
+    let op = match *self.peek_token()? {
+      CssToken::BracketClose => {
+        self.consume_token();
+        return Ok(AttributeSelector {
+          name,
+          op: None,
+          value: None,
+        });
+      }
+      CssToken::Delim('=') => AttrOp::Exact,
+      CssToken::Delim(c) => {
+        let op = match c {
+          '~' => AttrOp::Includes,
+          '|' => AttrOp::DashMatch,
+          '^' => AttrOp::Prefix,
+          '$' => AttrOp::Suffix,
+          '*' => AttrOp::Substring,
+          _ => return Err(self.unexpected()),
+        };
+        self.consume_token();
+        if !self.next_is(&CssToken::Delim('=')) {
+          return Err(self.unexpected());
+        }
+        op
+      }
+      _ => return Err(self.unexpected()),
+    };
+    self.consume_token();
+    self.skip_irrelevant()?;
+    let value = self.parse_attribute_value();
+    self.skip_irrelevant()?;
+    self.expect_token(&CssToken::BracketClose)?;
+
+    Ok(AttributeSelector {
+      name,
+      op: Some(op),
+      value: Some(value),
+    })
+  }
+
+  // Parse a pseudo-class given its name, e.g.: `hover` or `not` followed by `(.class)`
+  fn parse_pseudo_class(&mut self, name: String) -> Result<PseudoClass, CssError> {
+    match &*name.to_ascii_lowercase() {
+      "hover" => Ok(PseudoClass::Hover),
+      "focus" => Ok(PseudoClass::Focus),
+      "active" => Ok(PseudoClass::Active),
+      "visited" => Ok(PseudoClass::Visited),
+      "checked" => Ok(PseudoClass::Checked),
+      "disabled" => Ok(PseudoClass::Disabled),
+      "enabled" => Ok(PseudoClass::Enabled),
+      "first-child" => Ok(PseudoClass::FirstChild),
+      "not" => {
+        self.expect_token(&CssToken::ParenOpen)?;
+        self.skip_irrelevant()?;
+        let selector = self.parse_simple_selector()?;
+        self.skip_irrelevant()?;
+        self.expect_token(&CssToken::ParenClose)?;
+        Ok(PseudoClass::Not(Box::new(selector)))
+      }
+      _ => Err(CssError::UnknownPseudoClass(name)),
+    }
+  }
+
+  // Parse an attribute selector's value, which is a quoted string, an identifier, or
+  // a number as it is written.
+  fn parse_attribute_value(&mut self) -> String {
+    let value = match *self.next_token() {
+      CssToken::String(ref string) | CssToken::Ident(ref string) => string.clone(),
+      CssToken::Number(_)
+      | CssToken::Percentage(_)
+      | CssToken::Dimension(..) => self.token_text().to_string(),
+      _ => return String::new(),
+    };
+    self.consume_token();
+    value
+  }
+}
+
+// Expand a shorthand property into its longhands.
+// Any other declaration is returned as it is.
+fn expand_shorthand(declaration: Declaration) -> Result<Vec<Declaration>, CssError> {
+  match &*declaration.name {
+    "margin" | "padding" => expand_box_shorthand(declaration),
+    "border" | "border-top" | "border-right" | "border-bottom" | "border-left" => {
+      expand_border_shorthand(declaration)
+    }
+    _ => Ok(vec![declaration]),
+  }
+}
+
+// Expand `margin` or `padding` into its `-top`, `-right`, `-bottom` and `-left` longhands.
+// See: https://www.w3.org/TR/CSS2/box.html#propdef-margin
+fn expand_box_shorthand(declaration: Declaration) -> Result<Vec<Declaration>, CssError> {
+  let values = declaration.value.items();
+  // One value applies to all sides, two are vertical and horizontal,
+  // three are top, horizontal and bottom, and four go clockwise from the top.
+  let (top, right, bottom, left) = match values[..] {
+    [ref all] => (all, all, all, all),
+    [ref vertical, ref horizontal] => (vertical, horizontal, vertical, horizontal),
+    [ref top, ref horizontal, ref bottom] => (top, horizontal, bottom, horizontal),
+    [ref top, ref right, ref bottom, ref left] => (top, right, bottom, left),
+    _ => return Err(CssError::InvalidShorthand(declaration.name)),
+  };
+  Ok(
+    vec![("top", top), ("right", right), ("bottom", bottom), ("left", left)]
+      .into_iter()
+      .map(|(side, value)| Declaration {
+        name: format!("{}-{}", declaration.name, side),
+        value: value.clone(),
+        important: declaration.important,
+      })
+      .collect(),
+  )
+}
+
+// Expand `border` or a side like `border-top` into its `-width`, `-style` and `-color`
+// longhands. The values can come in any order, and the ones left out are reset to
+// their initial values.
+// See: https://www.w3.org/TR/CSS2/box.html#propdef-border
+fn expand_border_shorthand(declaration: Declaration) -> Result<Vec<Declaration>, CssError> {
+  let values = declaration.value.items().to_vec();
+  let mut width = None;
+  let mut style = None;
+  let mut color = None;
+  for value in values {
+    // Read `none` and `hidden` as border styles here, see `parse_value`.
+    let value = match value {
+      Value::Keyword(ref keyword) if border_style(keyword).is_some() => {
+        Value::BorderStyle(border_style(keyword).unwrap())
+      }
+      value => value,
+    };
+    let slot = match value {
+      Value::Length(..) | Value::Number(_) => &mut width,
+      Value::Keyword(ref keyword)
+        if matches!(&*keyword.to_ascii_lowercase(), "thin" | "medium" | "thick") =>
+      {
+        &mut width
+      }
+      Value::BorderStyle(_) => &mut style,
+      Value::ColorValue(_) => &mut color,
+      Value::Keyword(ref keyword) if keyword.eq_ignore_ascii_case("currentColor") => &mut color,
+      _ => return Err(CssError::InvalidShorthand(declaration.name)),
+    };
+    // Each part can only be given once.
+    if slot.replace(value).is_some() {
+      return Err(CssError::InvalidShorthand(declaration.name));
+    }
+  }
+  let longhands = vec![
+    ("width", width.unwrap_or_else(|| Value::Keyword("medium".to_string()))),
+    ("style", style.unwrap_or(Value::BorderStyle(BorderStyle::None))),
+    ("color", color.unwrap_or_else(|| Value::Keyword("currentColor".to_string()))),
+  ];
+  Ok(
+    longhands
+      .into_iter()
+      .map(|(part, value)| Declaration {
+        name: format!("{}-{}", declaration.name, part),
+        value,
+        important: declaration.important,
+      })
+      .collect(),
+  )
+}
+
+// Look up a border style by name, ignoring case.
+fn border_style(name: &str) -> Option<BorderStyle> {
+  match &*name.to_ascii_lowercase() {
+    "none" => Some(BorderStyle::None),
+    "hidden" => Some(BorderStyle::Hidden),
+    "dotted" => Some(BorderStyle::Dotted),
+    "dashed" => Some(BorderStyle::Dashed),
+    "solid" => Some(BorderStyle::Solid),
+    "double" => Some(BorderStyle::Double),
+    "groove" => Some(BorderStyle::Groove),
+    "ridge" => Some(BorderStyle::Ridge),
+    "inset" => Some(BorderStyle::Inset),
+    "outset" => Some(BorderStyle::Outset),
+    _ => None,
+  }
+}
+
+// Look up a pseudo-element by name, ignoring case.
+fn pseudo_element(name: &str) -> Option<PseudoElement> {
+  match &*name.to_ascii_lowercase() {
+    "before" => Some(PseudoElement::Before),
+    "after" => Some(PseudoElement::After),
+    "first-line" => Some(PseudoElement::FirstLine),
+    "first-letter" => Some(PseudoElement::FirstLetter),
+    "placeholder" => Some(PseudoElement::Placeholder),
+    "selection" => Some(PseudoElement::Selection),
+    _ => None,
+  }
+}
+
+// Look up a unit by name, ignoring case.
+fn parse_unit(name: &str) -> Result<Unit, CssError> {
+  match &*name.to_ascii_lowercase() {
+    "px" => Ok(Unit::Px),
+    "em" => Ok(Unit::Em),
+    "rem" => Ok(Unit::Rem),
+    "ex" => Ok(Unit::Ex),
+    "ch" => Ok(Unit::Ch),
+    "vh" => Ok(Unit::Vh),
+    "vw" => Ok(Unit::Vw),
+    "pt" => Ok(Unit::Pt),
+    "pc" => Ok(Unit::Pc),
+    "cm" => Ok(Unit::Cm),
+    "mm" => Ok(Unit::Mm),
+    "in" => Ok(Unit::In),
+    "q" => Ok(Unit::Q),
+    unit => Err(CssError::UnrecognizedUnit(unit.to_string())),
+  }
+}
+
+// Parse the digits of `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
+fn parse_hex_color(digits: String) -> Result<Value, CssError> {
+  if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err(CssError::InvalidHexColor(digits));
+  }
+  let hex = match digits.len() {
+    // Expand the shorthands by duplicating each digit: `#abc` -> `#aabbcc`.
+    3 | 4 => digits.chars().flat_map(|c| vec![c, c]).collect(),
+    6 | 8 => digits,
+    _ => return Err(CssError::InvalidHexColor(digits)),
+  };
+  Ok(Value::ColorValue(Color {
+    r: parse_hex_pair(&hex[0..2])?,
+    g: parse_hex_pair(&hex[2..4])?,
+    b: parse_hex_pair(&hex[4..6])?,
+    a: match hex.get(6..8) {
+      Some(pair) => parse_hex_pair(pair)?,
+      None => 255,
+    },
+  }))
+}
+
+// Parse two hexadecimal digits.
+fn parse_hex_pair(s: &str) -> Result<u8, CssError> {
+  u8::from_str_radix(s, 16).map_err(|_| CssError::InvalidHexColor(s.to_string()))
+}
+
+// Convert a 0-255 color component into a `u8`, rejecting anything out of range.
+// Percentages are scaled so that 100% is 255.
+fn color_channel(value: &Value, function: &str) -> Result<u8, CssError> {
+  let n = match *value {
+    Value::Number(n) => n,
+    Value::Length(n, Unit::Percent) => n * 2.55,
+    _ => return Err(CssError::InvalidArgument(function.to_string())),
+  };
+  if (0.0..=255.0).contains(&n) {
+    Ok(n.round() as u8)
+  } else {
+    Err(CssError::ColorOutOfRange(n))
+  }
+}
+
+// Convert a hue in degrees (0-360) and saturation and lightness (0.0-1.0) into RGB.
+// See the algorithm here: https://www.w3.org/TR/css-color-3/#hsl-color
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+  let t2 = if lightness <= 0.5 {
+    lightness * (saturation + 1.0)
+  } else {
+    lightness + saturation - lightness * saturation
+  };
+  let t1 = lightness * 2.0 - t2;
+  let hue = hue / 60.0;
+  let channel = |h: f32| (hue_to_rgb(t1, t2, h) * 255.0).round() as u8;
+
+  (channel(hue + 2.0), channel(hue), channel(hue - 2.0))
+}
+
+fn hue_to_rgb(t1: f32, t2: f32, hue: f32) -> f32 {
+  let hue = hue.rem_euclid(6.0);
+  if hue < 1.0 {
+    (t2 - t1) * hue + t1
+  } else if hue < 3.0 {
+    t2
+  } else if hue < 4.0 {
+    (t2 - t1) * (4.0 - hue) + t1
+  } else {
+    t1
+  }
+}
+
+// Convert a 0.0-1.0 or percentage alpha value into a 0-255 `u8`.
+fn alpha_channel(value: &Value, function: &str) -> Result<u8, CssError> {
+  let alpha = match *value {
+    Value::Number(n) => n,
+    Value::Length(n, Unit::Percent) => n / 100.0,
+    _ => return Err(CssError::InvalidArgument(function.to_string())),
+  };
+  if (0.0..=1.0).contains(&alpha) {
+    Ok((alpha * 255.0).round() as u8)
+  } else {
+    Err(CssError::ColorOutOfRange(alpha))
+  }
+}
+
+// Look up a CSS color keyword, ignoring case. `transparent` is transparent black.
+// `currentColor` depends on the element's `color` property, so it stays a keyword.
+// See the list here: https://www.w3.org/TR/css-color-4/#named-colors
+pub fn named_color(name: &str) -> Option<Color> {
+  let name = name.to_ascii_lowercase();
+  if name == "transparent" {
+    return Some(Color {
+      r: 0,
+      g: 0,
+      b: 0,
+      a: 0,
+    });
+  }
+  NAMED_COLORS
+    .binary_search_by_key(&&*name, |&(color_name, _)| color_name)
+    .ok()
+    .map(|index| {
+      let rgb = NAMED_COLORS[index].1;
+      Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+        a: 255,
+      }
+    })
+}
+
+// Named colors and their `0xrrggbb` values, sorted by name for binary search.
+const NAMED_COLORS: &[(&str, u32)] = &[
+  ("aliceblue", 0xf0f8ff),
+  ("antiquewhite", 0xfaebd7),
+  ("aqua", 0x00ffff),
+  ("aquamarine", 0x7fffd4),
+  ("azure", 0xf0ffff),
+  ("beige", 0xf5f5dc),
+  ("bisque", 0xffe4c4),
+  ("black", 0x000000),
+  ("blanchedalmond", 0xffebcd),
+  ("blue", 0x0000ff),
+  ("blueviolet", 0x8a2be2),
+  ("brown", 0xa52a2a),
+  ("burlywood", 0xdeb887),
+  ("cadetblue", 0x5f9ea0),
+  ("chartreuse", 0x7fff00),
+  ("chocolate", 0xd2691e),
+  ("coral", 0xff7f50),
+  ("cornflowerblue", 0x6495ed),
+  ("cornsilk", 0xfff8dc),
+  ("crimson", 0xdc143c),
+  ("cyan", 0x00ffff),
+  ("darkblue", 0x00008b),
+  ("darkcyan", 0x008b8b),
+  ("darkgoldenrod", 0xb8860b),
+  ("darkgray", 0xa9a9a9),
+  ("darkgreen", 0x006400),
+  ("darkgrey", 0xa9a9a9),
+  ("darkkhaki", 0xbdb76b),
+  ("darkmagenta", 0x8b008b),
+  ("darkolivegreen", 0x556b2f),
+  ("darkorange", 0xff8c00),
+  ("darkorchid", 0x9932cc),
+  ("darkred", 0x8b0000),
+  ("darksalmon", 0xe9967a),
+  ("darkseagreen", 0x8fbc8f),
+  ("darkslateblue", 0x483d8b),
+  ("darkslategray", 0x2f4f4f),
+  ("darkslategrey", 0x2f4f4f),
+  ("darkturquoise", 0x00ced1),
+  ("darkviolet", 0x9400d3),
+  ("deeppink", 0xff1493),
+  ("deepskyblue", 0x00bfff),
+  ("dimgray", 0x696969),
+  ("dimgrey", 0x696969),
+  ("dodgerblue", 0x1e90ff),
+  ("firebrick", 0xb22222),
+  ("floralwhite", 0xfffaf0),
+  ("forestgreen", 0x228b22),
+  ("fuchsia", 0xff00ff),
+  ("gainsboro", 0xdcdcdc),
+  ("ghostwhite", 0xf8f8ff),
+  ("gold", 0xffd700),
+  ("goldenrod", 0xdaa520),
+  ("gray", 0x808080),
+  ("green", 0x008000),
+  ("greenyellow", 0xadff2f),
+  ("grey", 0x808080),
+  ("honeydew", 0xf0fff0),
+  ("hotpink", 0xff69b4),
+  ("indianred", 0xcd5c5c),
+  ("indigo", 0x4b0082),
+  ("ivory", 0xfffff0),
+  ("khaki", 0xf0e68c),
+  ("lavender", 0xe6e6fa),
+  ("lavenderblush", 0xfff0f5),
+  ("lawngreen", 0x7cfc00),
+  ("lemonchiffon", 0xfffacd),
+  ("lightblue", 0xadd8e6),
+  ("lightcoral", 0xf08080),
+  ("lightcyan", 0xe0ffff),
+  ("lightgoldenrodyellow", 0xfafad2),
+  ("lightgray", 0xd3d3d3),
+  ("lightgreen", 0x90ee90),
+  ("lightgrey", 0xd3d3d3),
+  ("lightpink", 0xffb6c1),
+  ("lightsalmon", 0xffa07a),
+  ("lightseagreen", 0x20b2aa),
+  ("lightskyblue", 0x87cefa),
+  ("lightslategray", 0x778899),
+  ("lightslategrey", 0x778899),
+  ("lightsteelblue", 0xb0c4de),
+  ("lightyellow", 0xffffe0),
+  ("lime", 0x00ff00),
+  ("limegreen", 0x32cd32),
+  ("linen", 0xfaf0e6),
+  ("magenta", 0xff00ff),
+  ("maroon", 0x800000),
+  ("mediumaquamarine", 0x66cdaa),
+  ("mediumblue", 0x0000cd),
+  ("mediumorchid", 0xba55d3),
+  ("mediumpurple", 0x9370db),
+  ("mediumseagreen", 0x3cb371),
+  ("mediumslateblue", 0x7b68ee),
+  ("mediumspringgreen", 0x00fa9a),
+  ("mediumturquoise", 0x48d1cc),
+  ("mediumvioletred", 0xc71585),
+  ("midnightblue", 0x191970),
+  ("mintcream", 0xf5fffa),
+  ("mistyrose", 0xffe4e1),
+  ("moccasin", 0xffe4b5),
+  ("navajowhite", 0xffdead),
+  ("navy", 0x000080),
+  ("oldlace", 0xfdf5e6),
+  ("olive", 0x808000),
+  ("olivedrab", 0x6b8e23),
+  ("orange", 0xffa500),
+  ("orangered", 0xff4500),
+  ("orchid", 0xda70d6),
+  ("palegoldenrod", 0xeee8aa),
+  ("palegreen", 0x98fb98),
+  ("paleturquoise", 0xafeeee),
+  ("palevioletred", 0xdb7093),
+  ("papayawhip", 0xffefd5),
+  ("peachpuff", 0xffdab9),
+  ("peru", 0xcd853f),
+  ("pink", 0xffc0cb),
+  ("plum", 0xdda0dd),
+  ("powderblue", 0xb0e0e6),
+  ("purple", 0x800080),
+  ("rebeccapurple", 0x663399),
+  ("red", 0xff0000),
+  ("rosybrown", 0xbc8f8f),
+  ("royalblue", 0x4169e1),
+  ("saddlebrown", 0x8b4513),
+  ("salmon", 0xfa8072),
+  ("sandybrown", 0xf4a460),
+  ("seagreen", 0x2e8b57),
+  ("seashell", 0xfff5ee),
+  ("sienna", 0xa0522d),
+  ("silver", 0xc0c0c0),
+  ("skyblue", 0x87ceeb),
+  ("slateblue", 0x6a5acd),
+  ("slategray", 0x708090),
+  ("slategrey", 0x708090),
+  ("snow", 0xfffafa),
+  ("springgreen", 0x00ff7f),
+  ("steelblue", 0x4682b4),
+  ("tan", 0xd2b48c),
+  ("teal", 0x008080),
+  ("thistle", 0xd8bfd8),
+  ("tomato", 0xff6347),
+  ("turquoise", 0x40e0d0),
+  ("violet", 0xee82ee),
+  ("wheat", 0xf5deb3),
+  ("white", 0xffffff),
+  ("whitesmoke", 0xf5f5f5),
+  ("yellow", 0xffff00),
+  ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The value of the first declaration of the first rule in `css`.
+  fn first_value(css: &str) -> Value {
+    let stylesheet = parse(css.to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    rule.declarations[0].value.clone()
+  }
+
+  fn rgba(r: u8, g: u8, b: u8, a: u8) -> Value {
+    Value::ColorValue(Color { r, g, b, a })
+  }
+
+  // The only selector in `source`.
+  fn selector(source: &str) -> Selector {
+    let mut selectors = parse_selector_list(source.to_string()).unwrap();
+    assert_eq!(selectors.len(), 1);
+    selectors.remove(0)
+  }
+
+  // The value of the first declaration of each rule in `css`.
+  fn first_values(css: &str) -> Vec<Value> {
+    let stylesheet = parse(css.to_string()).unwrap();
+    stylesheet
+      .style_rules()
+      .map(|rule| rule.declarations[0].value.clone())
+      .collect()
+  }
+
+  #[test]
+  fn short_long_and_alpha_hex_colors_are_the_same() {
+    let expected = Value::ColorValue(Color { r: 0xaa, g: 0xbb, b: 0xcc, a: 0xff });
+    assert_eq!(first_value("a { color: #abc }"), expected);
+    assert_eq!(first_value("a { color: #aabbcc }"), expected);
+    assert_eq!(first_value("a { color: #aabbccff }"), expected);
+  }
+
+  #[test]
+  fn multibyte_char_at_the_end_of_the_input() {
+    let (stylesheet, errors) = parse_with_errors("a { color: red }\u{e9}".to_string());
+    assert_eq!(stylesheet.style_rules().count(), 1);
+    assert_eq!(errors[0].error, CssError::UnexpectedChar('\u{e9}'));
+    assert_eq!(errors[0].position, 16);
+
+    let source = "a { content: \"\u{65e5}";
+    let error = parse(source.to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnexpectedEof);
+    assert_eq!(error.position, source.len());
+  }
+
+  #[test]
+  fn parse_error_reports_its_position() {
+    let error = parse("a { color: red }\nb > !c { }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnexpectedChar('!'));
+    assert_eq!(error.position, 21);
+    assert_eq!((error.line, error.column), (2, 5));
+  }
+
+  #[test]
+  fn truncated_input_is_an_error() {
+    let error = parse(".foo {".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnexpectedEof);
+    assert_eq!(error.position, 6);
+
+    // Every prefix of a stylesheet parses or fails without panicking.
+    let source = "@media screen { a.b > #c:hover, d[e=\"f\"] { margin: 1px -2em !important } }";
+    for (i, _) in source.char_indices() {
+      let _ = parse_with_errors(source[..i].to_string());
+    }
+  }
+
+  #[test]
+  fn parse_error_on_line_3_is_reported_on_line_3() {
+    let source = "a {\n  color: red;\n  margin: 1px 2px ^;\n}";
+    let error = parse(source.to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnexpectedChar('^'));
+    assert_eq!((error.line, error.column), (3, 19));
+    assert_eq!(error.to_string(), "unexpected character '^' at line 3, column 19");
+  }
+
+  #[test]
+  fn values_are_read_from_whole_tokens() {
+    assert_eq!(first_value("a { margin: 1e1px }"), Value::Length(10.0, Unit::Px));
+    assert_eq!(first_value("a { margin: +5px }"), Value::Length(5.0, Unit::Px));
+    assert_eq!(first_value("a { content: \"a\\\"b\" }"), Value::String("a\"b".to_string()));
+    let stylesheet = parse(".\\31 0 { }".to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    let Selector::Simple(selector) = &rule.selectors[0] else { panic!() };
+    assert_eq!(selector.class, ["10"]);
+  }
+
+  #[test]
+  fn a_bad_url_is_an_invalid_argument() {
+    let (stylesheet, errors) = parse_with_errors("@import url(a b.css);\na { }".to_string());
+    assert_eq!(stylesheet.rules.len(), 1);
+    assert_eq!(errors[0].error, CssError::InvalidArgument("url".to_string()));
+    assert_eq!(errors[0].position, 8);
+  }
+
+  #[test]
+  fn rgb_rgba_and_hex_colors_are_the_same() {
+    let css = "a { color: #ff0080 } b { color: rgb(255,0,128) } c { color: rgb( 255 , 0 , 128 ) }\
+               d { color: rgba(255, 0, 128, 1) }";
+    let colors = first_values(css);
+    assert!(colors.iter().all(|color| *color == colors[0]));
+    let half = first_value("a { color: rgba(255, 0, 128, 0.5) }");
+    assert!(matches!(half, Value::ColorValue(Color { a: 127 | 128, .. })));
+    let error = parse("a { color: rgb(256, 0, 0) }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::ColorOutOfRange(256.0));
+  }
+
+  #[test]
+  fn percentages_are_lengths_with_their_own_unit() {
+    let values = first_values("a { width: 50% } b { width: 1.5em } c { width: 2rem }");
+    assert_eq!(
+      values,
+      [
+        Value::Length(50.0, Unit::Percent),
+        Value::Length(1.5, Unit::Em),
+        Value::Length(2.0, Unit::Rem),
+      ]
+    );
+  }
+
+  #[test]
+  fn comments_in_a_declaration_block_are_skipped() {
+    let stylesheet = parse("a { /* x */ color: red; /* y */ }".to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    assert_eq!(rule.declarations.len(), 1);
+    assert_eq!(rule.declarations[0].name, "color");
+
+    let error = parse("a { color: red } /* x".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnterminatedComment);
+    assert_eq!(error.position, 17);
+  }
+
+  #[test]
+  fn important_annotation_sets_the_flag() {
+    let stylesheet = parse("a { display: block !important ; width: 0 }".to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    assert!(rule.declarations[0].important);
+    assert_eq!(rule.declarations[0].value, Value::Keyword("block".to_string()));
+    assert!(!rule.declarations[1].important);
+  }
+
+  #[test]
+  fn child_combinator_joins_two_simple_selectors() {
+    let complex = match selector("div.note > p#intro") {
+      Selector::Complex(complex) => complex,
+      selector => panic!("{:?} is not complex", selector),
+    };
+    assert_eq!(complex.first.tag_name.as_deref(), Some("div"));
+    assert_eq!(complex.first.class, ["note"]);
+    assert_eq!(complex.rest.len(), 1);
+    let (ref combinator, ref last) = complex.rest[0];
+    assert_eq!(*combinator, Combinator::Child);
+    assert_eq!((last.tag_name.as_deref(), last.id.as_deref()), (Some("p"), Some("intro")));
+    assert_eq!(complex.specificity(), (1, 1, 2));
+    assert_eq!(selector("div p").specificity(), (0, 0, 2));
+  }
+
+  #[test]
+  fn comments_are_skipped_between_selectors_declarations_and_values() {
+    let css = "a /* 1 */, /* 2 */ b { margin: 1px /* 3 */ 2px; /* 4 /* 5 */ color: red }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    assert_eq!(rule.selectors.len(), 2);
+    assert_eq!(rule.to_string(), "a, b { margin-top: 1px; margin-right: 2px; \
+                                  margin-bottom: 1px; margin-left: 2px; color: #ff0000; }");
+  }
+
+  #[test]
+  fn relative_length_units_round_trip() {
+    for (css, unit) in [
+      ("1em", Unit::Em),
+      ("2rem", Unit::Rem),
+      ("3%", Unit::Percent),
+      ("4vh", Unit::Vh),
+      ("5vw", Unit::Vw),
+    ] {
+      let value = first_value(&format!("a {{ width: {} }}", css));
+      assert_eq!(value.to_string(), css);
+      assert!(matches!(value, Value::Length(_, ref u) if *u == unit));
+    }
+  }
+
+  #[test]
+  fn physical_units_convert_to_pixels() {
+    let values = first_values("a { width: 1in } b { width: 2.54cm } c { width: 72pt } \
+                               d { width: 6pc } e { width: 25.4mm } f { width: 101.6Q }");
+    for value in values {
+      let (length, unit) = match value {
+        Value::Length(length, unit) => (length, unit),
+        value => panic!("{:?} is not a length", value),
+      };
+      let px = f64::from(length) * unit.to_px_factor().unwrap();
+      assert!((px - 96.0).abs() < 0.001, "{}{} is {}px", length, unit, px);
+    }
+    assert_eq!(Unit::Em.to_px_factor(), None);
+  }
+
+  #[test]
+  fn four_digit_hex_colors_have_alpha() {
+    let expected = Value::ColorValue(Color { r: 0xaa, g: 0xbb, b: 0xcc, a: 0xdd });
+    assert_eq!(first_value("a { color: #abcd }"), expected);
+    assert_eq!(first_value("a { color: #AABBCCDD }"), expected);
+    let error = parse("a { color: #abcde }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::InvalidHexColor("abcde".to_string()));
+  }
+
+  #[test]
+  fn rgb_takes_numbers_or_percentages_and_scales_alpha() {
+    assert_eq!(first_value("a { color: rgb(100%, 20%, 0%) }"), rgba(255, 51, 0, 255));
+    assert_eq!(first_value("a { color: rgba(1, 2, 3, 0) }"), rgba(1, 2, 3, 0));
+    assert_eq!(first_value("a { color: rgba(1, 2, 3, 25%) }"), rgba(1, 2, 3, 64));
+    let error = parse("a { color: rgb(1, 2) }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::WrongArgumentCount("rgb".to_string(), 2));
+  }
+
+  #[test]
+  fn hsl_converts_to_rgb() {
+    assert_eq!(first_value("a { color: hsl(120, 100%, 50%) }"), rgba(0, 255, 0, 255));
+    assert_eq!(first_value("a { color: hsla(120, 100%, 50%, 0.4) }"), rgba(0, 255, 0, 102));
+    // The hue wraps around, and saturation and lightness are clamped.
+    assert_eq!(first_value("a { color: hsl(-240, 100%, 50%) }"), rgba(0, 255, 0, 255));
+    assert_eq!(first_value("a { color: hsl(480, 150%, 50%) }"), rgba(0, 255, 0, 255));
+    assert_eq!(first_value("a { color: hsl(0, 0%, 120%) }"), rgba(255, 255, 255, 255));
+  }
+
+  #[test]
+  fn named_colors_are_colors_in_any_case() {
+    assert_eq!(named_color("RebeccaPurple"), Some(Color { r: 102, g: 51, b: 153, a: 255 }));
+    assert_eq!(named_color("not-a-color"), None);
+    assert_eq!(first_value("a { color: AliceBlue }"), rgba(240, 248, 255, 255));
+    let current_color = Value::Keyword("currentColor".to_string());
+    assert_eq!(first_value("a { color: currentColor }"), current_color);
+  }
+
+  #[test]
+  fn attribute_selectors_parse_every_operator() {
+    let simple = match selector("a[b][c=d][e~=\"f g\"][h|=i][j^=k][l$=m][n*=o]") {
+      Selector::Simple(simple) => simple,
+      selector => panic!("{:?} is not simple", selector),
+    };
+    let attributes: Vec<(&str, Option<&AttrOp>, Option<&str>)> = simple
+      .attributes
+      .iter()
+      .map(|attr| (&*attr.name, attr.op.as_ref(), attr.value.as_deref()))
+      .collect();
+    assert_eq!(
+      attributes,
+      [
+        ("b", None, None),
+        ("c", Some(&AttrOp::Exact), Some("d")),
+        ("e", Some(&AttrOp::Includes), Some("f g")),
+        ("h", Some(&AttrOp::DashMatch), Some("i")),
+        ("j", Some(&AttrOp::Prefix), Some("k")),
+        ("l", Some(&AttrOp::Suffix), Some("m")),
+        ("n", Some(&AttrOp::Substring), Some("o")),
+      ]
+    );
+    assert_eq!(simple.specificity(), (0, 7, 1));
+  }
+
+  #[test]
+  fn pseudo_classes_count_like_classes() {
+    let source = "a:hover:focus:active:visited:checked:disabled:enabled";
+    assert_eq!(selector(source).specificity(), (0, 7, 1));
+    assert_eq!(selector(source).to_string(), source);
+
+    // `:not()` counts as its argument.
+    let simple = match selector("p:not(#x)") {
+      Selector::Simple(simple) => simple,
+      selector => panic!("{:?} is not simple", selector),
+    };
+    match simple.pseudo_classes[..] {
+      [PseudoClass::Not(ref not)] => assert_eq!(not.id.as_deref(), Some("x")),
+      ref pseudo_classes => panic!("{:?}", pseudo_classes),
+    }
+    assert_eq!(simple.specificity(), (1, 0, 1));
+
+    let error = parse_selector_list("a:hovering".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnknownPseudoClass("hovering".to_string()));
+  }
+
+  #[test]
+  fn pseudo_elements_count_like_tags() {
+    let pseudo_element = |source| match selector(source) {
+      Selector::Simple(simple) => (simple.specificity(), simple.pseudo_element),
+      selector => panic!("{:?} is not simple", selector),
+    };
+    assert_eq!(pseudo_element("p::before"), ((0, 0, 2), Some(PseudoElement::Before)));
+    assert_eq!(pseudo_element("p:after"), ((0, 0, 2), Some(PseudoElement::After)));
+    assert_eq!(pseudo_element(".a::selection"), ((0, 1, 1), Some(PseudoElement::Selection)));
+    assert!(parse_selector_list("p:selection".to_string()).is_err());
+    assert!(parse_selector_list("p::before::after".to_string()).is_err());
+  }
+
+  #[test]
+  fn combinators_chain_simple_selectors() {
+    let complex = match selector("ul  li>a+b~ .c") {
+      Selector::Complex(complex) => complex,
+      selector => panic!("{:?} is not complex", selector),
+    };
+    let combinators: Vec<&Combinator> = complex.rest.iter().map(|(c, _)| c).collect();
+    assert_eq!(
+      combinators,
+      [
+        &Combinator::Descendant,
+        &Combinator::Child,
+        &Combinator::AdjacentSibling,
+        &Combinator::GeneralSibling,
+      ]
+    );
+    assert_eq!(complex.specificity(), (0, 1, 4));
+    assert_eq!(selector("ul  li>a+b~ .c").to_string(), "ul li > a + b ~ .c");
+  }
+
+  #[test]
+  fn media_queries_have_a_type_and_conditions() {
+    let css = "@media screen {} @media (max-width: 768px) {} \
+               @media screen and (min-width: 320px) and (color) {}";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let queries: Vec<&MediaQuery> = stylesheet
+      .rules
+      .iter()
+      .map(|item| match *item {
+        CssItem::AtRule(AtRule::Media(ref media)) => &media.queries[0],
+        ref item => panic!("{:?} is not @media", item),
+      })
+      .collect();
+    assert_eq!(queries[0].media_type.as_deref(), Some("screen"));
+    assert!(queries[0].conditions.is_empty());
+    assert_eq!(queries[1].media_type, None);
+    assert_eq!(queries[1].conditions[0].feature, "max-width");
+    assert_eq!(queries[1].conditions[0].value, Some(Value::Length(768.0, Unit::Px)));
+    assert_eq!(queries[2].media_type.as_deref(), Some("screen"));
+    let features: Vec<&str> = queries[2].conditions.iter().map(|c| &*c.feature).collect();
+    assert_eq!(features, ["min-width", "color"]);
+    assert_eq!(queries[2].conditions[1].value, None);
+  }
+
+  #[test]
+  fn hex_colors_of_the_wrong_length_or_digits_are_errors() {
+    for css in ["a { color: #ab }", "a { color: #xyzzzz }", "a { color: #abcdefab1 }"] {
+      let error = parse(css.to_string()).unwrap_err();
+      assert!(matches!(error.error, CssError::InvalidHexColor(_)), "{}: {:?}", css, error);
+    }
+    assert_eq!(first_value("a { color: #0a0B0c }"), rgba(10, 11, 12, 255));
+  }
+
+  #[test]
+  fn keyframes_animate_numbers_and_colors() {
+    let css = "@keyframes slide-in { from { opacity: 0; color: #000 } 50%, to { opacity: 1 } \
+               to { color: red } }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let keyframes = match stylesheet.rules[0] {
+      CssItem::AtRule(AtRule::Keyframes(ref keyframes)) => keyframes,
+      ref item => panic!("{:?} is not @keyframes", item),
+    };
+    assert_eq!(keyframes.name, "slide-in");
+    let steps: Vec<(&[KeyframeSelector], Vec<&Value>)> = keyframes
+      .keyframes
+      .iter()
+      .map(|keyframe| {
+        let values = keyframe.declarations.iter().map(|d| &d.value).collect();
+        (&keyframe.selectors[..], values)
+      })
+      .collect();
+    assert_eq!(
+      steps,
+      [
+        (&[KeyframeSelector::From][..], vec![&Value::Number(0.0), &rgba(0, 0, 0, 255)]),
+        (
+          &[KeyframeSelector::Percentage(50.0), KeyframeSelector::To][..],
+          vec![&Value::Number(1.0)]
+        ),
+        (&[KeyframeSelector::To][..], vec![&rgba(255, 0, 0, 255)]),
+      ]
+    );
+  }
+
+  #[test]
+  fn font_face_sources_are_urls_with_formats() {
+    let css = "@font-face { font-family: \"Open Sans\"; font-weight: 700; \
+               src: url(a.woff2) format(\"woff2\"), url('a.woff') format('woff') }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let font_face = match stylesheet.rules[0] {
+      CssItem::AtRule(AtRule::FontFace(ref font_face)) => font_face,
+      ref item => panic!("{:?} is not @font-face", item),
+    };
+    let declarations = &font_face.declarations;
+    assert_eq!(declarations[0].value, Value::String("Open Sans".to_string()));
+    let source = |url: &str, format: &str| {
+      Value::List(vec![Value::Url(url.to_string()), Value::Format(format.to_string())])
+    };
+    assert_eq!(declarations[1].value, Value::Number(700.0));
+    assert_eq!(
+      declarations[2].value,
+      Value::CommaList(vec![source("a.woff2", "woff2"), source("a.woff", "woff")])
+    );
+  }
+
+  #[test]
+  fn negative_and_leading_dot_lengths() {
+    let values = first_values("a { top: -10px } b { top: .5em } c { top: -.25rem }");
+    assert_eq!(
+      values,
+      [
+        Value::Length(-10.0, Unit::Px),
+        Value::Length(0.5, Unit::Em),
+        Value::Length(-0.25, Unit::Rem),
+      ]
+    );
+  }
+
+  #[test]
+  fn variables_are_parsed_and_resolved() {
+    let css = "html { --main-color: #f00; --gap: 4px } \
+               a { color: var(--main-color); margin-left: var(--missing, 2px); top: var(--none) }\
+               b { padding-left: var( --gap ) }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let rule = stylesheet.style_rules().nth(1).unwrap();
+    assert_eq!(rule.declarations[0].value, Value::Variable("--main-color".to_string()));
+    assert_eq!(
+      rule.declarations[1].value,
+      Value::VariableWithFallback("--missing".to_string(), Box::new(Value::Length(2.0, Unit::Px)))
+    );
+
+    let resolved = resolve_variables(&stylesheet);
+    let values: Vec<&Value> = resolved
+      .style_rules()
+      .flat_map(|rule| &rule.declarations)
+      .map(|declaration| &declaration.value)
+      .collect();
+    assert_eq!(
+      values[2..],
+      [
+        &rgba(255, 0, 0, 255),
+        &Value::Length(2.0, Unit::Px),
+        &Value::Variable("--none".to_string()),
+        &Value::Length(4.0, Unit::Px),
+      ]
+    );
+  }
+
+  #[test]
+  fn media_block_holds_its_own_rules() {
+    let css = "a { color: red } @media print { b { color: blue } c { display: none } }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    assert_eq!(stylesheet.rules.len(), 2);
+    assert_eq!(stylesheet.style_rules().count(), 1);
+    let media = match stylesheet.rules[1] {
+      CssItem::AtRule(AtRule::Media(ref media)) => media,
+      ref item => panic!("{:?} is not @media", item),
+    };
+    assert_eq!(media.queries[0].media_type.as_deref(), Some("print"));
+    assert_eq!(media.rules.len(), 2);
+    assert_eq!(media.rules[0].selectors[0].to_string(), "b");
+    assert_eq!(media.rules[1].selectors[0].to_string(), "c");
+  }
+
+  #[test]
+  fn bad_declarations_and_rules_are_skipped() {
+    let css = "a { color: red; width: 10qq; display: block } b >> c { color: red } d { top: 0 }";
+    let (stylesheet, errors) = parse_with_errors(css.to_string());
+    let names: Vec<&str> = stylesheet
+      .style_rules()
+      .flat_map(|rule| &rule.declarations)
+      .map(|declaration| &*declaration.name)
+      .collect();
+    assert_eq!(names, ["color", "display", "top"]);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].error, CssError::UnrecognizedUnit("qq".to_string()));
+    assert_eq!(errors[1].error, CssError::UnexpectedChar('>'));
+  }
+
+  #[test]
+  fn calc_multiplies_before_adding() {
+    let px = |n| Box::new(CalcExpr::Length(n, Unit::Px));
+    assert_eq!(
+      first_value("a { width: calc(100% - 20px) }"),
+      Value::Calc(CalcExpr::Sub(Box::new(CalcExpr::Percentage(100.0)), px(20.0)))
+    );
+    let product = CalcExpr::Mul(px(2.0), Box::new(CalcExpr::Number(3.0)));
+    assert_eq!(
+      first_value("a { width: calc(1px + 2px * 3) }"),
+      Value::Calc(CalcExpr::Add(px(1.0), Box::new(product)))
+    );
+    let nested = first_value("a { width: calc((1px + 2px) * 3) }");
+    assert_eq!(nested.to_string(), "calc((1px + 2px) * 3)");
+    assert_eq!(nested.to_px(0.0, 16.0), 9.0);
+    assert_eq!(first_value("a { width: calc(100% - 20px) }").to_px(200.0, 16.0), 180.0);
+  }
+
+  #[test]
+  fn missing_semicolon_is_an_error_at_the_next_colon() {
+    let error = parse("a { color: red\n  width: 0 }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::UnexpectedChar(':'));
+    assert_eq!((error.position, error.line, error.column), (22, 2, 8));
+  }
+
+  #[test]
+  fn margin_and_padding_expand_for_one_to_four_values() {
+    let sides = |css: &str| {
+      let stylesheet = parse(css.to_string()).unwrap();
+      let rule = stylesheet.style_rules().next().unwrap();
+      assert_eq!(rule.selectors[0].specificity(), (0, 1, 1));
+      let sides: Vec<String> = rule
+        .declarations
+        .iter()
+        .map(|d| format!("{}={}{}", d.name, d.value, if d.important { "!" } else { "" }))
+        .collect();
+      sides.join(" ")
+    };
+    assert_eq!(
+      sides("p.a { margin: 1px }"),
+      "margin-top=1px margin-right=1px margin-bottom=1px margin-left=1px"
+    );
+    assert_eq!(
+      sides("p.a { padding: 1px 2px }"),
+      "padding-top=1px padding-right=2px padding-bottom=1px padding-left=2px"
+    );
+    assert_eq!(
+      sides("p.a { margin: 1px 2px 3px }"),
+      "margin-top=1px margin-right=2px margin-bottom=3px margin-left=2px"
+    );
+    assert_eq!(
+      sides("p.a { padding: 1px 2px 3px 4px !important }"),
+      "padding-top=1px! padding-right=2px! padding-bottom=3px! padding-left=4px!"
+    );
+    let error = parse("a { margin: 1px 2px 3px 4px 5px }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::InvalidShorthand("margin".to_string()));
+  }
+
+  #[test]
+  fn border_expands_into_width_style_and_color() {
+    let rule = |css: &str| {
+      let stylesheet = parse(css.to_string()).unwrap();
+      let rule = stylesheet.style_rules().next().unwrap();
+      rule.to_string()
+    };
+    assert_eq!(
+      rule("a { border: 1px solid #333 }"),
+      "a { border-width: 1px; border-style: solid; border-color: #333333; }"
+    );
+    assert_eq!(
+      rule("a { border-top: dashed }"),
+      "a { border-top-width: medium; border-top-style: dashed; border-top-color: currentColor; }"
+    );
+    assert_eq!(
+      first_value("a { border-left: red none thick }"),
+      Value::Keyword("thick".to_string())
+    );
+    let error = parse("a { border: 1px 2px }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::InvalidShorthand("border".to_string()));
+  }
+
+  #[test]
+  fn important_flag_is_only_set_by_an_important_annotation() {
+    let css = "a { top: 0 ! IMPORTANT; left: 0; content: \"!important\" }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    let important: Vec<bool> = rule.declarations.iter().map(|d| d.important).collect();
+    assert_eq!(important, [true, false, false]);
+    let error = parse("a { top: 0 !urgent }".to_string()).unwrap_err();
+    assert_eq!(error.error, CssError::InvalidAnnotation("urgent".to_string()));
+  }
+
+  #[test]
+  fn lengths_resolve_against_the_parent_and_font_size() {
+    let to_px = |value: Value| value.to_px(300.0, 10.0);
+    assert_eq!(to_px(Value::Length(12.0, Unit::Px)), 12.0);
+    assert_eq!(to_px(Value::Length(2.0, Unit::Em)), 20.0);
+    assert_eq!(to_px(Value::Length(50.0, Unit::Percent)), 150.0);
+    assert_eq!(to_px(Value::Length(2.0, Unit::Rem)), 2.0 * DEFAULT_FONT_SIZE);
+    assert_eq!(to_px(Value::Length(1.0, Unit::In)), 96.0);
+    assert_eq!(to_px(Value::Length(10.0, Unit::Vw)), 0.0);
+    assert_eq!(to_px(Value::Keyword("auto".to_string())), 0.0);
+    assert_eq!(to_px(rgba(0, 0, 0, 255)), 0.0);
+  }
+
+  #[test]
+  fn space_and_comma_separated_values_are_lists() {
+    let keyword = |name: &str| Value::Keyword(name.to_string());
+    assert_eq!(
+      first_value("a { font-family: Arial, sans-serif }"),
+      Value::CommaList(vec![keyword("Arial"), keyword("sans-serif")])
+    );
+    let shadow = |x, y, color| {
+      Value::List(vec![Value::Length(x, Unit::Px), Value::Length(y, Unit::Px), color])
+    };
+    let red = shadow(1.0, 2.0, rgba(255, 0, 0, 255));
+    let blue = shadow(3.0, 4.0, rgba(0, 0, 255, 255));
+    assert_eq!(
+      first_value("a { box-shadow: 1px 2px red, 3px 4px #00f }"),
+      Value::CommaList(vec![red, blue])
+    );
+    let stylesheet = parse("a { margin: 10px 20px 30px 40px; }".to_string()).unwrap();
+    let rule = stylesheet.style_rules().next().unwrap();
+    let lengths: Vec<f32> = rule.declarations.iter().map(|d| d.value.to_px(0.0, 0.0)).collect();
+    assert_eq!(lengths, [10.0, 20.0, 30.0, 40.0]);
+  }
+
+  #[test]
+  fn hover_and_first_child_bump_specificity() {
+    for (source, pseudo_class) in [("a:hover", "Hover"), ("li:first-child", "FirstChild")] {
+      let simple = match selector(source) {
+        Selector::Simple(simple) => simple,
+        selector => panic!("{:?} is not simple", selector),
+      };
+      assert_eq!(format!("{:?}", simple.pseudo_classes), format!("[{}]", pseudo_class));
+      assert_eq!(simple.specificity(), (0, 1, 1));
+      assert_eq!(simple.to_string(), source);
+    }
+  }
+
+  #[test]
+  fn parsed_rules_can_be_read() {
+    let stylesheet = parse("h1 { font-size: 2em } p { color: blue }".to_string()).unwrap();
+    let mut declarations = Vec::new();
+    for rule in stylesheet.style_rules() {
+      for declaration in &rule.declarations {
+        declarations.push((rule.selectors[0].to_string(), declaration.name.clone()));
+      }
+    }
+    assert_eq!(
+      declarations,
+      [("h1".to_string(), "font-size".to_string()), ("p".to_string(), "color".to_string())]
+    );
+    let rule = stylesheet.style_rules().nth(1).unwrap();
+    assert_eq!(rule.declarations[0].value, rgba(0, 0, 255, 255));
+  }
+
+  #[test]
+  fn stylesheet_serializes_back_to_css() {
+    let css = "a, #b.c { color: #AbC; width: 10px; display: block !important }\n\
+               @media screen and (max-width: 100px) { p { margin-top: -.5em } }";
+    let expected = "#b.c, a { color: #aabbcc; width: 10px; display: block !important; }\n\
+                    @media screen and (max-width: 100px) { p { margin-top: -0.5em; } }\n";
+    let stylesheet = parse(css.to_string()).unwrap();
+    assert_eq!(stylesheet.to_css(), expected);
+    assert_eq!(parse(expected.to_string()).unwrap().to_css(), expected);
+  }
+
+  #[test]
+  fn red_and_transparent_are_colors() {
+    assert_eq!(first_value("a { color: red }"), rgba(255, 0, 0, 255));
+    assert_eq!(first_value("a { background: TRANSPARENT }"), rgba(0, 0, 0, 0));
+    let basic = "black silver gray white maroon red purple fuchsia green lime olive yellow navy \
+                 blue teal aqua";
+    assert!(basic.split(' ').all(|name| named_color(name).is_some()));
+  }
+
+  #[test]
+  fn colors_format_as_hex_and_rgba() {
+    let color = match first_value("a { color: #aabbcc }") {
+      Value::ColorValue(color) => color,
+      value => panic!("{:?} is not a color", value),
+    };
+    assert_eq!(color.to_hex(), "#aabbcc");
+    assert_eq!(color.to_css_rgba(), "rgba(170, 187, 204, 1)");
+    let translucent = Color { r: 1, g: 2, b: 3, a: 128 };
+    assert_eq!(translucent.to_hex(), "#01020380");
+    assert_eq!(translucent.to_css_rgba(), "rgba(1, 2, 3, 0.502)");
+  }
+
+  #[test]
+  fn imports_are_listed_in_order() {
+    let css = "@import url(\"a.css\");\n@import 'b.css' print;\np { color: red }";
+    let stylesheet = parse(css.to_string()).unwrap();
+    assert_eq!(stylesheet.imports().collect::<Vec<_>>(), ["a.css", "b.css"]);
+    assert_eq!(stylesheet.style_rules().count(), 1);
+
+    let (stylesheet, errors) = parse_with_errors("p {} @import \"c.css\";".to_string());
+    assert_eq!(stylesheet.imports().count(), 0);
+    assert_eq!(errors[0].error, CssError::MisplacedImport);
+  }
+
+  #[test]
+  fn quoted_and_unquoted_urls_are_the_same() {
+    let values = first_values(
+      "a { background: url(a.png) } b { background: url(\"a.png\") } \
+       c { background: url( 'a.png' ) }",
+    );
+    assert_eq!(values, vec![Value::Url("a.png".to_string()); 3]);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn stylesheet_json_round_trip() {
+    let source = "@import url(\"d.css\");\n\
+                  @media screen { a.b > #c:hover { color: #abc; margin: 1px -2em !important } }";
+    let stylesheet = parse(source.to_string()).unwrap();
+    let json = serde_json::to_string(&stylesheet).unwrap();
+    let round_tripped: Stylesheet = serde_json::from_str(&json).unwrap();
+    assert_eq!(round_tripped.to_css(), stylesheet.to_css());
+  }
+
+  // Time parsing a 1MB stylesheet. Run it with `cargo test --release -- --ignored`.
+  #[test]
+  #[ignore]
+  fn bench_parse_a_1mb_stylesheet() {
+    let rule = |i| {
+      format!(
+        ".c{0} > a#x{0}:hover, div[data-a=\"{0}\"] {{ color: #abc; margin: 1px 2em -3px 4% }}\n",
+        i
+      )
+    };
+    let source: String = (0..12_000).map(rule).collect();
+    assert!(source.len() > 1_000_000);
+    let start = std::time::Instant::now();
+    let stylesheet = parse(source).unwrap();
+    eprintln!("parsed {} rules in {:?}", stylesheet.rules.len(), start.elapsed());
   }
 }
@@ -0,0 +1,536 @@
+use super::css_parser::CssError;
+
+// Splitting CSS source into tokens, as the first step of parsing.
+// See: https://www.w3.org/TR/css-syntax-3/#tokenization
+
+// A function like `rgb(` is an `Ident` followed by `ParenOpen`, and a quoted `url("a.png")`
+// is tokenized the same way. Only an unquoted URL is a single `Url` token, since it can
+// hold characters that aren't valid in any other token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssToken {
+  Ident(String),
+  AtKeyword(String), // `@media`, without the `@`.
+  Hash(String),      // `#fff` or `#main`, without the `#`.
+  String(String),    // The contents of a quoted string, with escapes resolved.
+  Url(String),       // `url(a.png)`
+  BadUrl,            // An unquoted URL with whitespace, a quote or `(` in it.
+  Number(f32),
+  Percentage(f32),
+  Dimension(f32, String), // A number with a unit, e.g. `10px`.
+  Delim(char),            // Any other single character, e.g. `>` or `.`.
+  WhiteSpace,
+  Comment(String),
+  Colon,
+  Semicolon,
+  Comma,
+  BraceOpen,
+  BraceClose,
+  BracketOpen,
+  BracketClose,
+  ParenOpen,
+  ParenClose,
+  Eof,
+}
+
+// Split a stylesheet into tokens. The last token is always `Eof`.
+// Tokenizing never fails: as the spec says, an unterminated string, comment or URL ends
+// at the end of the input.
+pub fn tokenize(source: &str) -> Vec<CssToken> {
+  let mut tokenizer = Tokenizer::new(source);
+  let mut tokens = Vec::new();
+  loop {
+    let token = tokenizer.next_token();
+    let eof = token == CssToken::Eof;
+    tokens.push(token);
+    if eof {
+      return tokens;
+    }
+  }
+}
+
+pub struct Tokenizer<'a> {
+  position: usize,
+  input: &'a str,
+  // The first string, comment or URL left unterminated, see `take_error`.
+  error: Option<(usize, CssError)>,
+}
+
+impl<'a> Tokenizer<'a> {
+  pub fn new(input: &'a str) -> Tokenizer<'a> {
+    Tokenizer {
+      position: 0,
+      input,
+      error: None,
+    }
+  }
+
+  // The byte offset that the next token starts at.
+  pub fn position(&self) -> usize {
+    self.position
+  }
+
+  // Take the error found in the last token, with the position to report it at. Only an
+  // unterminated string, comment or URL is an error, so the last token runs to the end
+  // of the input and nothing but `Eof` follows it.
+  pub fn take_error(&mut self) -> Option<(usize, CssError)> {
+    self.error.take()
+  }
+
+  // Read the current character without consuming it, or `None` at the end of the input.
+  fn next_char(&self) -> Option<char> {
+    self.input[self.position..].chars().next()
+  }
+
+  // Read the character `n` characters after the current one.
+  fn nth_char(&self, n: usize) -> Option<char> {
+    self.input[self.position..].chars().nth(n)
+  }
+
+  // Do the next characters start with the given string?
+  fn starts_with(&self, s: &str) -> bool {
+    self.input[self.position..].starts_with(s)
+  }
+
+  // Return the current character, and advance past all of its bytes.
+  fn consume_char(&mut self) -> Option<char> {
+    let c = self.next_char()?;
+    self.position += c.len_utf8();
+    Some(c)
+  }
+
+  // Consume characters until `test` returns false
+  fn consume_while<F>(&mut self, test: F) -> &'a str
+  where
+    F: Fn(char) -> bool,
+  {
+    let start = self.position;
+    while self.next_char().is_some_and(&test) {
+      self.consume_char();
+    }
+    &self.input[start..self.position]
+  }
+
+  // Remember an error, unless one was already found.
+  fn record_error(&mut self, position: usize, error: CssError) {
+    self.error.get_or_insert((position, error));
+  }
+
+  // Consume the next token.
+  pub fn next_token(&mut self) -> CssToken {
+    if self.starts_with("/*") {
+      return self.consume_comment();
+    }
+    if self.starts_with_number() {
+      return self.consume_numeric();
+    }
+    if self.starts_with_ident() {
+      return self.consume_ident_like();
+    }
+    let c = match self.consume_char() {
+      Some(c) => c,
+      None => return CssToken::Eof,
+    };
+    match c {
+      c if c.is_whitespace() => {
+        self.consume_while(char::is_whitespace);
+        CssToken::WhiteSpace
+      }
+      '"' | '\'' => self.consume_string(c),
+      '#' if self.next_char().is_some_and(is_name_char) || self.starts_with_escape() => {
+        CssToken::Hash(self.consume_name())
+      }
+      '@' if self.starts_with_ident() => CssToken::AtKeyword(self.consume_name()),
+      ':' => CssToken::Colon,
+      ';' => CssToken::Semicolon,
+      ',' => CssToken::Comma,
+      '{' => CssToken::BraceOpen,
+      '}' => CssToken::BraceClose,
+      '[' => CssToken::BracketOpen,
+      ']' => CssToken::BracketClose,
+      '(' => CssToken::ParenOpen,
+      ')' => CssToken::ParenClose,
+      c => CssToken::Delim(c),
+    }
+  }
+
+  // `/* ... */`. Comments do not nest, so a `/*` inside a comment is just part of it.
+  fn consume_comment(&mut self) -> CssToken {
+    let start = self.position;
+    self.position += "/*".len();
+    let text = match self.input[self.position..].find("*/") {
+      Some(end) => {
+        let text = &self.input[self.position..self.position + end];
+        self.position += end + "*/".len();
+        text
+      }
+      None => {
+        self.record_error(start, CssError::UnterminatedComment);
+        let text = &self.input[self.position..];
+        self.position = self.input.len();
+        text
+      }
+    };
+    CssToken::Comment(text.to_string())
+  }
+
+  // The rest of a string after its opening quote. A `\` escapes the next character, and
+  // an escaped newline continues the string on the next line.
+  fn consume_string(&mut self, quote: char) -> CssToken {
+    let mut string = String::new();
+    loop {
+      match self.next_char() {
+        None => {
+          self.record_error(self.input.len(), CssError::UnexpectedEof);
+          break;
+        }
+        Some(c) if c == quote => {
+          self.consume_char();
+          break;
+        }
+        Some('\\') if self.nth_char(1) == Some('\n') => self.position += "\\\n".len(),
+        Some('\\') if self.nth_char(1).is_none() => self.position += "\\".len(),
+        Some('\\') => string.push(self.consume_escape()),
+        Some(c) => {
+          self.consume_char();
+          string.push(c);
+        }
+      }
+    }
+    CssToken::String(string)
+  }
+
+  // An identifier, a function name followed by `(`, or an unquoted `url(...)`.
+  fn consume_ident_like(&mut self) -> CssToken {
+    let name = self.consume_name();
+    if name.eq_ignore_ascii_case("url") && self.starts_with("(") {
+      let mut after_paren = self.input[self.position + 1..].chars();
+      let quoted = after_paren
+        .find(|c| !c.is_whitespace())
+        .is_some_and(|c| c == '"' || c == '\'');
+      if !quoted {
+        self.consume_char();
+        return self.consume_url();
+      }
+    }
+    CssToken::Ident(name)
+  }
+
+  // The rest of an unquoted `url(...)`, after the `(`. It may only have whitespace around
+  // the URL, not in it.
+  // See: https://www.w3.org/TR/css-syntax-3/#consume-url-token
+  fn consume_url(&mut self) -> CssToken {
+    self.consume_while(char::is_whitespace);
+    let mut url = String::new();
+    loop {
+      match self.next_char() {
+        None => {
+          self.record_error(self.input.len(), CssError::UnexpectedEof);
+          return CssToken::Url(url);
+        }
+        Some(')') => {
+          self.consume_char();
+          return CssToken::Url(url);
+        }
+        Some(c) if c.is_whitespace() => {
+          self.consume_while(char::is_whitespace);
+          if !matches!(self.next_char(), None | Some(')')) {
+            return self.consume_bad_url();
+          }
+        }
+        Some('"' | '\'' | '(') => return self.consume_bad_url(),
+        Some('\\') if self.starts_with_escape() => url.push(self.consume_escape()),
+        Some('\\') => return self.consume_bad_url(),
+        Some(c) => {
+          self.consume_char();
+          url.push(c);
+        }
+      }
+    }
+  }
+
+  // Skip the rest of a URL that isn't valid, up to and including its `)`.
+  fn consume_bad_url(&mut self) -> CssToken {
+    loop {
+      match self.next_char() {
+        None => {
+          self.record_error(self.input.len(), CssError::UnexpectedEof);
+          return CssToken::BadUrl;
+        }
+        Some(')') => {
+          self.consume_char();
+          return CssToken::BadUrl;
+        }
+        Some('\\') if self.starts_with_escape() => {
+          self.consume_escape();
+        }
+        Some(_) => {
+          self.consume_char();
+        }
+      }
+    }
+  }
+
+  // A number, followed by `%` or a unit if there is one.
+  fn consume_numeric(&mut self) -> CssToken {
+    let number = self.consume_number();
+    if self.starts_with_ident() {
+      CssToken::Dimension(number, self.consume_name())
+    } else if self.starts_with("%") {
+      self.consume_char();
+      CssToken::Percentage(number)
+    } else {
+      CssToken::Number(number)
+    }
+  }
+
+  // A number like `10`, `-.5`, `+1.5` or `1e3`. `starts_with_number` must be true.
+  fn consume_number(&mut self) -> f32 {
+    let start = self.position;
+    if self.starts_with("+") || self.starts_with("-") {
+      self.consume_char();
+    }
+    self.consume_while(|c| c.is_ascii_digit());
+    if self.starts_with(".") && self.nth_char(1).is_some_and(|c| c.is_ascii_digit()) {
+      self.consume_char();
+      self.consume_while(|c| c.is_ascii_digit());
+    }
+    // An exponent, as long as it isn't the start of a unit like `em`.
+    if matches!(self.next_char(), Some('e' | 'E')) {
+      let first_digit = match self.nth_char(1) {
+        Some('+' | '-') => 2,
+        _ => 1,
+      };
+      if self.nth_char(first_digit).is_some_and(|c| c.is_ascii_digit()) {
+        for _ in 0..first_digit {
+          self.consume_char();
+        }
+        self.consume_while(|c| c.is_ascii_digit());
+      }
+    }
+    // Only digits, a `.` between them and an exponent were consumed, so this parses,
+    // though a number too large for an `f32` becomes infinite.
+    self.input[start..self.position].parse().unwrap_or(0.0)
+  }
+
+  // A run of name characters and escapes.
+  fn consume_name(&mut self) -> String {
+    let mut name = String::new();
+    loop {
+      match self.next_char() {
+        Some(c) if is_name_char(c) => {
+          self.consume_char();
+          name.push(c);
+        }
+        Some('\\') if self.starts_with_escape() => name.push(self.consume_escape()),
+        _ => return name,
+      }
+    }
+  }
+
+  // `\` followed by up to six hex digits and an optional space giving a code point, or by
+  // any other character standing for itself. `starts_with_escape` must be true.
+  // See: https://www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+  fn consume_escape(&mut self) -> char {
+    self.consume_char();
+    let hex = self.input[self.position..]
+      .chars()
+      .take(6)
+      .take_while(char::is_ascii_hexdigit)
+      .count();
+    if hex == 0 {
+      return self.consume_char().unwrap_or('\u{fffd}');
+    }
+    let digits = &self.input[self.position..self.position + hex];
+    self.position += hex;
+    if self.next_char().is_some_and(char::is_whitespace) {
+      self.consume_char();
+    }
+    match u32::from_str_radix(digits, 16).map(char::from_u32) {
+      Ok(Some(c)) if c != '\0' => c,
+      _ => '\u{fffd}',
+    }
+  }
+
+  // Is the next character a `\` that starts an escape?
+  fn starts_with_escape(&self) -> bool {
+    self.starts_with("\\") && !matches!(self.nth_char(1), None | Some('\n'))
+  }
+
+  // Do the next characters start an identifier, e.g. `a`, `-webkit-` or `--name`?
+  fn starts_with_ident(&self) -> bool {
+    match self.next_char() {
+      Some('-') => match self.nth_char(1) {
+        Some('-') => true,
+        Some('\\') => !matches!(self.nth_char(2), None | Some('\n')),
+        Some(c) => is_name_start_char(c),
+        None => false,
+      },
+      Some('\\') => self.starts_with_escape(),
+      Some(c) => is_name_start_char(c),
+      None => false,
+    }
+  }
+
+  // Do the next characters start a number, e.g. `1`, `.5`, `-1` or `+.5`?
+  fn starts_with_number(&self) -> bool {
+    let mut chars = self.input[self.position..].chars();
+    let mut c = chars.next();
+    if matches!(c, Some('+' | '-')) {
+      c = chars.next();
+    }
+    match c {
+      Some('0'..='9') => true,
+      Some('.') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+      _ => false,
+    }
+  }
+}
+
+// Names are limited to ASCII, unlike in the spec, so any other character is a `Delim`.
+fn is_name_start_char(c: char) -> bool {
+  c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+  is_name_start_char(c) || c.is_ascii_digit() || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use CssToken::*;
+
+  #[test]
+  fn tokenizes_a_rule() {
+    assert_eq!(
+      tokenize("#a > .b { color: red; }"),
+      vec![
+        Hash("a".to_string()),
+        WhiteSpace,
+        Delim('>'),
+        WhiteSpace,
+        Delim('.'),
+        Ident("b".to_string()),
+        WhiteSpace,
+        BraceOpen,
+        WhiteSpace,
+        Ident("color".to_string()),
+        Colon,
+        WhiteSpace,
+        Ident("red".to_string()),
+        Semicolon,
+        WhiteSpace,
+        BraceClose,
+        Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn tokenizes_numbers_percentages_and_dimensions() {
+    assert_eq!(
+      tokenize("1 -2.5 .5 1e3px 50% +5em"),
+      vec![
+        Number(1.0),
+        WhiteSpace,
+        Number(-2.5),
+        WhiteSpace,
+        Number(0.5),
+        WhiteSpace,
+        Dimension(1000.0, "px".to_string()),
+        WhiteSpace,
+        Percentage(50.0),
+        WhiteSpace,
+        Dimension(5.0, "em".to_string()),
+        Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn tokenizes_at_keywords_and_functions() {
+    assert_eq!(
+      tokenize("@media rgb(1,2)"),
+      vec![
+        AtKeyword("media".to_string()),
+        WhiteSpace,
+        Ident("rgb".to_string()),
+        ParenOpen,
+        Number(1.0),
+        Comma,
+        Number(2.0),
+        ParenClose,
+        Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn resolves_escapes_in_strings_and_identifiers() {
+    assert_eq!(
+      tokenize(r#""a\"b" 'c\41 d' \31 0"#),
+      vec![
+        String("a\"b".to_string()),
+        WhiteSpace,
+        String("cAd".to_string()),
+        WhiteSpace,
+        Ident("10".to_string()),
+        Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn only_an_unquoted_url_is_a_url_token() {
+    assert_eq!(
+      tokenize("url( a.png ) url(\"b.png\")"),
+      vec![
+        Url("a.png".to_string()),
+        WhiteSpace,
+        Ident("url".to_string()),
+        ParenOpen,
+        String("b.png".to_string()),
+        ParenClose,
+        Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn a_url_with_whitespace_inside_is_a_bad_url() {
+    assert_eq!(tokenize("url(a b) c"), vec![BadUrl, WhiteSpace, Ident("c".to_string()), Eof]);
+  }
+
+  #[test]
+  fn comments_are_tokens() {
+    assert_eq!(
+      tokenize("a/* b /* c */d"),
+      vec![Ident("a".to_string()), Comment(" b /* c ".to_string()), Ident("d".to_string()), Eof]
+    );
+  }
+
+  #[test]
+  fn eof_after_consuming_a_final_multibyte_char() {
+    let mut tokenizer = Tokenizer::new("a\u{e9}");
+    assert_eq!(tokenizer.next_token(), Ident("a".to_string()));
+    assert_eq!(tokenizer.position(), 1);
+    assert_eq!(tokenizer.next_token(), Delim('\u{e9}'));
+    assert_eq!(tokenizer.position(), 3);
+    assert_eq!(tokenizer.next_token(), Eof);
+  }
+
+  #[test]
+  fn unterminated_tokens_end_at_the_end_of_the_input() {
+    let mut tokenizer = Tokenizer::new("a /* b");
+    tokenizer.next_token();
+    tokenizer.next_token();
+    assert_eq!(tokenizer.take_error(), None);
+    assert_eq!(tokenizer.next_token(), Comment(" b".to_string()));
+    assert_eq!(tokenizer.take_error(), Some((2, CssError::UnterminatedComment)));
+    assert_eq!(tokenizer.next_token(), Eof);
+
+    let mut tokenizer = Tokenizer::new("'abc");
+    assert_eq!(tokenizer.next_token(), String("abc".to_string()));
+    assert_eq!(tokenizer.take_error(), Some((4, CssError::UnexpectedEof)));
+  }
+}
@@ -1,44 +1,463 @@
-use std::collections::HashMap;
+use super::html_parser::{RAW_TEXT_ELEMENTS, VOID_ELEMENTS};
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::{Rc, Weak};
 
-#[derive(Debug)]
+// Nodes are shared with `Rc` and link to their parent and siblings with `Weak`, so the tree
+// can be walked in any direction. The links are set as the tree is built, and a node can't
+// be changed once it is in the tree, so the style and layout trees can borrow it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "NodeData"))]
 pub struct Node {
   // data common to all nodes.
-  pub children: Vec<Node>,
+  pub children: Vec<Rc<Node>>,
   // data specific to each node type.
   pub node_type: NodeType,
+  // Set once this node's parent is put in an `Rc`, see `into_rc`.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  parent: OnceCell<Weak<Node>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  previous_sibling: OnceCell<Weak<Node>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  next_sibling: OnceCell<Weak<Node>>,
+}
+
+// The links between nodes are left out, since they would go on forever.
+impl fmt::Debug for Node {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Node")
+      .field("children", &self.children)
+      .field("node_type", &self.node_type)
+      .finish()
+  }
+}
+
+// A deserialized node, which is made into a `Node` to link its children.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct NodeData {
+  children: Vec<Node>,
+  node_type: NodeType,
+}
+
+#[cfg(feature = "serde")]
+impl From<NodeData> for Node {
+  fn from(data: NodeData) -> Node {
+    Node::new(data.children, data.node_type)
+  }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NodeType {
   // You can See all node types here: https://dom.spec.whatwg.org/#dom-node-nodetype.
-  // Element and Text are only implemented in this project for simplicity.
+  // Only a few of them are implemented in this project for simplicity.
   Element(ElementData),
   Text(String),
+  Comment(String),
+  Doctype {
+    name: String,
+    public_id: Option<String>,
+    system_id: Option<String>,
+  },
+  // The root of a parsed document. Its children are the top-level nodes.
+  Document(DocumentData),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DocumentData {
+  pub doctype: Option<Box<Node>>,
+}
+
+impl Node {
+  // Iterate over this node and all of its descendants in document (pre-order) order.
+  pub fn descendants(&self) -> Descendants<'_> {
+    Descendants { stack: vec![self] }
+  }
+
+  // The text of this node and all of its descendants, concatenated in document order.
+  pub fn text_content(&self) -> String {
+    self
+      .descendants()
+      .filter_map(|node| match node.node_type {
+        NodeType::Text(ref text) => Some(text.as_str()),
+        _ => None,
+      })
+      .collect()
+  }
+
+  // Find the first element in this node and its descendants whose `id` is `id`.
+  pub fn get_element_by_id(&self, id: &str) -> Option<&Node> {
+    self.descendants().find(|node| match node.node_type {
+      NodeType::Element(ref elem) => elem.id() == Some(id),
+      _ => false,
+    })
+  }
+
+  // Find every element in this node and its descendants with the given tag name,
+  // ignoring case, in document order. `*` matches every element.
+  pub fn get_elements_by_tag_name<'a>(&'a self, tag: &str) -> Vec<&'a Node> {
+    self
+      .descendants()
+      .filter(|node| match node.node_type {
+        NodeType::Element(ref elem) => tag == "*" || elem.tag_name.eq_ignore_ascii_case(tag),
+        _ => false,
+      })
+      .collect()
+  }
+
+  // Find every element in this node and its descendants that has all of the given
+  // space-separated class names, in document order. No class names match nothing.
+  pub fn get_elements_by_class_name<'a>(&'a self, class: &str) -> Vec<&'a Node> {
+    let wanted: Vec<&str> = class.split_whitespace().collect();
+    if wanted.is_empty() {
+      return Vec::new();
+    }
+    self
+      .descendants()
+      .filter(|node| match node.node_type {
+        NodeType::Element(ref elem) => {
+          let classes = elem.classes();
+          wanted.iter().all(|class| classes.contains(class))
+        }
+        _ => false,
+      })
+      .collect()
+  }
+
+  // The node this node is a child of, or `None` for the root.
+  pub fn parent(&self) -> Option<Rc<Node>> {
+    self.parent.get()?.upgrade()
+  }
+
+  // The node after this one in its parent.
+  pub fn next_sibling(&self) -> Option<Rc<Node>> {
+    self.next_sibling.get()?.upgrade()
+  }
+
+  // The node before this one in its parent.
+  pub fn prev_sibling(&self) -> Option<Rc<Node>> {
+    self.previous_sibling.get()?.upgrade()
+  }
+
+  // Put the root of a tree in an `Rc`, linking its children to it.
+  // A document's doctype is linked to it too, but it has no siblings, since it isn't
+  // one of the children.
+  pub fn into_rc(self) -> Rc<Node> {
+    Rc::new_cyclic(|this| {
+      for child in &self.children {
+        let _ = child.parent.set(this.clone());
+      }
+      if let NodeType::Document(DocumentData {
+        doctype: Some(ref doctype),
+      }) = self.node_type
+      {
+        let _ = doctype.parent.set(this.clone());
+      }
+      self
+    })
+  }
+
+  // Make a node with the given children, linking them to one another.
+  fn new(children: Vec<Node>, node_type: NodeType) -> Node {
+    let children: Vec<Rc<Node>> = children.into_iter().map(Node::into_rc).collect();
+    for pair in children.windows(2) {
+      let _ = pair[0].next_sibling.set(Rc::downgrade(&pair[1]));
+      let _ = pair[1].previous_sibling.set(Rc::downgrade(&pair[0]));
+    }
+    Node {
+      children,
+      node_type,
+      parent: OnceCell::new(),
+      previous_sibling: OnceCell::new(),
+      next_sibling: OnceCell::new(),
+    }
+  }
+
+  // Serialize this node and its descendants back into HTML, like `outerHTML`.
+  // Unlike `dom::to_html`, nothing is indented, so text is written exactly as it is.
+  pub fn to_html(&self) -> String {
+    let mut html = String::new();
+    write_html(self, None, &mut html);
+    html
+  }
+
+  // Serialize the children of this node back into HTML, like `innerHTML`.
+  pub fn inner_html(&self) -> String {
+    let mut html = String::new();
+    write_children(self, None, &mut html);
+    html
+  }
+}
+
+// Iterator returned by `Node::descendants`.
+// The stack holds the nodes still to visit, next one last.
+pub struct Descendants<'a> {
+  stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+  type Item = &'a Node;
+
+  fn next(&mut self) -> Option<&'a Node> {
+    let node = self.stack.pop()?;
+    self.stack.extend(node.children.iter().rev().map(|child| &**child));
+    // The doctype comes before the other children of a document.
+    if let NodeType::Document(DocumentData {
+      doctype: Some(ref doctype),
+    }) = node.node_type
+    {
+      self.stack.push(doctype);
+    }
+    Some(node)
+  }
 }
 
 pub type AttrMap = HashMap<String, String>;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ElementData {
   pub tag_name: String,
   pub attributes: AttrMap,
 }
 
+impl ElementData {
+  // The value of the `id` attribute, if it is present and not empty.
+  pub fn id(&self) -> Option<&str> {
+    match self.attributes.get("id") {
+      Some(id) if !id.is_empty() => Some(id),
+      _ => None,
+    }
+  }
+
+  // The names in the whitespace-separated `class` attribute.
+  pub fn classes(&self) -> HashSet<&str> {
+    match self.attributes.get("class") {
+      Some(class_list) => class_list.split_whitespace().collect(),
+      None => HashSet::new(),
+    }
+  }
+}
+
 // Constructor function to make it easy to create new text nodes.
 pub fn text(data: String) -> Node {
-  Node {
-    children: Vec::new(),
-    node_type: NodeType::Text(data),
-  }
+  Node::new(Vec::new(), NodeType::Text(data))
+}
+
+// Constructor function to make it easy to create new comment nodes.
+pub fn comment(data: String) -> Node {
+  Node::new(Vec::new(), NodeType::Comment(data))
+}
+
+// Constructor function to make it easy to create new doctype nodes.
+pub fn doctype(name: String, public_id: Option<String>, system_id: Option<String>) -> Node {
+  let doctype = NodeType::Doctype {
+    name,
+    public_id,
+    system_id,
+  };
+  Node::new(Vec::new(), doctype)
+}
+
+// Constructor function to make it easy to create new document nodes.
+// A doctype among `children` is moved into the document data.
+pub fn document(mut children: Vec<Node>) -> Node {
+  let doctype = children
+    .iter()
+    .position(|child| matches!(child.node_type, NodeType::Doctype { .. }))
+    .map(|index| Box::new(children.remove(index)));
+  Node::new(children, NodeType::Document(DocumentData { doctype }))
 }
 
 // Constructor function to make it easy to create new element nodes.
 pub fn elem(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
-  Node {
-    children: children,
-    node_type: NodeType::Element(ElementData {
-      tag_name: name,
-      attributes: attrs,
-    }),
+  let element = NodeType::Element(ElementData {
+    tag_name: name,
+    attributes: attrs,
+  });
+  Node::new(children, element)
+}
+
+// Serialize a node and its descendants back into HTML, indenting nested nodes by depth.
+// Attributes are written in name order, so the output is stable.
+pub fn to_html(node: &Node) -> String {
+  let mut html = String::new();
+  write_html(node, Some(0), &mut html);
+  html
+}
+
+// Write `node` as HTML. With a depth, each node goes on its own line, indented by the
+// depth. Without one, the nodes are written exactly as they are in the tree.
+fn write_html(node: &Node, depth: Option<usize>, html: &mut String) {
+  let indent = "  ".repeat(depth.unwrap_or(0));
+  let newline = if depth.is_some() { "\n" } else { "" };
+  match node.node_type {
+    NodeType::Element(ref elem) => {
+      let mut attributes: Vec<_> = elem.attributes.iter().collect();
+      attributes.sort();
+      html.push_str(&indent);
+      html.push('<');
+      html.push_str(&elem.tag_name);
+      for (name, value) in attributes {
+        html.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+      }
+      if VOID_ELEMENTS.contains(&&*elem.tag_name.to_ascii_lowercase()) {
+        html.push_str(" />");
+        html.push_str(newline);
+        return;
+      }
+      html.push('>');
+      html.push_str(newline);
+      write_children(node, depth.map(|depth| depth + 1), html);
+      html.push_str(&format!("{}</{}>{}", indent, elem.tag_name, newline));
+    }
+    // Indenting stands in for the whitespace around text, so whitespace-only text is left
+    // out.
+    NodeType::Text(ref text) if depth.is_some() => {
+      if !text.trim().is_empty() {
+        html.push_str(&format!("{}{}\n", indent, escape(text.trim())))
+      }
+    }
+    NodeType::Text(ref text) => html.push_str(&escape(text)),
+    NodeType::Comment(ref data) => {
+      html.push_str(&format!("{}<!--{}-->{}", indent, data, newline))
+    }
+    NodeType::Doctype {
+      ref name,
+      ref public_id,
+      ref system_id,
+    } => {
+      html.push_str(&format!("{}<!DOCTYPE {}", indent, name));
+      match (public_id, system_id) {
+        (Some(public_id), Some(system_id)) => {
+          html.push_str(&format!(" PUBLIC \"{}\" \"{}\"", public_id, system_id))
+        }
+        (Some(public_id), None) => html.push_str(&format!(" PUBLIC \"{}\"", public_id)),
+        (None, Some(system_id)) => html.push_str(&format!(" SYSTEM \"{}\"", system_id)),
+        (None, None) => {}
+      }
+      html.push('>');
+      html.push_str(newline);
+    }
+    NodeType::Document(ref document) => {
+      if let Some(ref doctype) = document.doctype {
+        write_html(doctype, depth, html);
+      }
+      write_children(node, depth, html);
+    }
+  }
+}
+
+// Write the children of `node` as HTML, see `write_html`.
+fn write_children(node: &Node, depth: Option<usize>, html: &mut String) {
+  let raw_text = match node.node_type {
+    NodeType::Element(ref elem) => {
+      RAW_TEXT_ELEMENTS.contains(&&*elem.tag_name.to_ascii_lowercase())
+    }
+    _ => false,
+  };
+  for child in &node.children {
+    match child.node_type {
+      // Scripts and styles are written as they are, since they can't contain entities.
+      NodeType::Text(ref text) if raw_text => {
+        html.push_str(&"  ".repeat(depth.unwrap_or(0)));
+        html.push_str(text);
+        if depth.is_some() {
+          html.push('\n');
+        }
+      }
+      _ => write_html(child, depth, html),
+    }
+  }
+}
+
+// Replace the characters that have special meaning in HTML with character references.
+fn escape(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::html_parser;
+
+  #[test]
+  fn siblings_link_forward_and_backward() {
+    let root = html_parser::parse("<ul><li>a</li><li>b</li><li>c</li></ul>".to_string()).unwrap();
+    let list = root.children[0].clone();
+    let mut forward = Vec::new();
+    let mut item = Some(list.children[0].clone());
+    while let Some(node) = item {
+      forward.push(node.text_content());
+      item = node.next_sibling();
+    }
+    assert_eq!(forward, ["a", "b", "c"]);
+    let mut backward = Vec::new();
+    let mut item = Some(list.children[2].clone());
+    while let Some(node) = item {
+      backward.push(node.text_content());
+      item = node.prev_sibling();
+    }
+    assert_eq!(backward, ["c", "b", "a"]);
+  }
+
+  #[test]
+  fn parent_links_up_to_the_document() {
+    let root = html_parser::parse("<div><p>a</p></div>".to_string()).unwrap();
+    let text = root.children[0].children[0].children[0].clone();
+    let p = text.parent().unwrap();
+    assert!(Rc::ptr_eq(&p, &root.children[0].children[0]));
+    let div = p.parent().unwrap();
+    assert!(Rc::ptr_eq(&div.parent().unwrap(), &root));
+    assert!(root.parent().is_none());
+  }
+
+  #[test]
+  fn the_doctype_links_to_the_document_and_comes_first() {
+    let root = html_parser::parse("<!DOCTYPE html><p>a</p>".to_string()).unwrap();
+    let doctype = root.descendants().nth(1).unwrap();
+    assert!(matches!(doctype.node_type, NodeType::Doctype { .. }));
+    assert!(Rc::ptr_eq(&doctype.parent().unwrap(), &root));
+    assert!(doctype.next_sibling().is_none());
+    assert_eq!(root.descendants().count(), 4);
+  }
+
+  #[test]
+  fn text_content_joins_the_text_of_nested_elements_in_order() {
+    let html = "<div>a<p>b<b>c</b></p><!-- x -->d<span></span>e</div>";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    assert_eq!(root.text_content(), "abcde");
+    assert_eq!(root.children[0].children[1].text_content(), "bc");
+    assert_eq!(root.children[0].children[4].text_content(), "");
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn document_json_round_trip() {
+    let html = "<!DOCTYPE html><html><body id=\"a\" class=\"b c\"><!-- d --><p>e &amp; f</p>\
+                </body></html>";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let json = serde_json::to_string(&root).unwrap();
+    let round_tripped = serde_json::from_str::<Node>(&json).unwrap().into_rc();
+    assert_eq!(round_tripped.to_html(), root.to_html());
+    assert!(round_tripped.to_html().starts_with("<!DOCTYPE html>"));
+    // The links between nodes are made again.
+    let body = &round_tripped.children[0].children[0];
+    assert!(Rc::ptr_eq(&body.children[0].next_sibling().unwrap(), &body.children[1]));
+    assert!(Rc::ptr_eq(&body.parent().unwrap(), &round_tripped.children[0]));
   }
 }
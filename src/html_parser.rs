@@ -1,154 +1,489 @@
 use super::dom;
-use std::collections::HashMap;
+use super::html_tokenizer::{HtmlToken, Tokenizer, TokenizerState, RCDATA_ELEMENTS};
+use super::location;
+use std::fmt;
+use std::rc::Rc;
 
-// Parse an HTML document and return the root element.
-pub fn parse(source: String) -> dom::Node {
-  let mut parser = Parser {
-    position: 0,
+// Parse an HTML document and return a document node holding the top-level nodes.
+pub fn parse(source: String) -> Result<Rc<dom::Node>, ParseError> {
+  HtmlParser {
     input: source,
-  };
-  let mut nodes = parser.parse_nodes();
-  // If the document contains a root element, just return it.
-  // Otherwise, create one.
-  if nodes.len() == 1 {
-    nodes.swap_remove(0)
-  } else {
-    dom::elem("html".to_string(), HashMap::new(), nodes)
+    ..HtmlParser::new()
   }
+  .finish()
 }
 
-struct Parser {
-  position: usize, // "usize" is an unsigned integer, similar to "size_t" in C language.
-  input: String,
+// Options for `parse_with_options`. The defaults give the same tree as `parse`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+  // Collapse each run of whitespace in text to a single space, and trim it at the start
+  // and end of an element's contents, dropping text that is left empty. Whitespace-only
+  // text between two nodes is kept as a single space, e.g. in `<b>a</b> <i>b</i>`.
+  // The contents of `<pre>`, raw text and RCDATA elements are left as they are.
+  pub collapse_whitespace: bool,
 }
 
-impl Parser {
-  // Read the current character without consuming it.
-  fn next_char(&self) -> char {
-    self.input[self.position..].chars().next().unwrap()
+// Like `parse`, with the given options.
+pub fn parse_with_options(
+  source: String,
+  options: ParseOptions,
+) -> Result<Rc<dom::Node>, ParseError> {
+  HtmlParser {
+    input: source,
+    ..HtmlParser::with_options(options)
   }
-  // Do the cext characters start with the given string?
-  fn starts_with(&self, s: &str) -> bool {
-    self.input[self.position..].starts_with(s)
+  .finish()
+}
+
+// Does an element keep the whitespace in its contents when it is collapsed?
+fn keeps_whitespace(tag_name: &str) -> bool {
+  tag_name == "pre" || RAW_TEXT_ELEMENTS.contains(&tag_name) || RCDATA_ELEMENTS.contains(&tag_name)
+}
+
+// Collapse whitespace in the text among `nodes`, the contents of an element or the
+// top-level nodes, see `ParseOptions`.
+fn collapse_whitespace(nodes: &mut Vec<dom::Node>) {
+  let last = nodes.len().saturating_sub(1);
+  for (i, node) in nodes.iter_mut().enumerate() {
+    if let dom::NodeType::Text(ref mut text) = node.node_type {
+      let words: Vec<&str> = text
+        .split(|c: char| c.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+      let mut collapsed = String::new();
+      if words.is_empty() {
+        // Whitespace-only text separates the nodes on either side of it.
+        if i > 0 && i < last {
+          collapsed.push(' ');
+        }
+      } else {
+        // Keep the spaces between the text and the nodes next to it.
+        if i > 0 && text.starts_with(|c: char| c.is_ascii_whitespace()) {
+          collapsed.push(' ');
+        }
+        collapsed.push_str(&words.join(" "));
+        if i < last && text.ends_with(|c: char| c.is_ascii_whitespace()) {
+          collapsed.push(' ');
+        }
+      }
+      *text = collapsed;
+    }
   }
-  // Return true if all input is consumed.
-  fn eof(&self) -> bool {
-    self.position >= self.input.len()
+  nodes.retain(|node| !matches!(node.node_type, dom::NodeType::Text(ref text) if text.is_empty()));
+}
+
+// Parses a document that arrives in chunks, e.g. from a network response, giving the
+// same tree as `parse` however the document is split.
+// The document is split into tokens, see `html_tokenizer`, and each token is added to the
+// tree once all of it has arrived. Errors are only reported by `finish`.
+pub struct HtmlParser {
+  input: String,
+  // Where tokenizing has got to in `input`.
+  tokenizer: TokenizerState,
+  // The elements whose end tags haven't been reached yet, outermost first.
+  open_elements: Vec<OpenElement>,
+  // The top-level nodes.
+  nodes: Vec<dom::Node>,
+  doctype_allowed: bool, // Only one doctype is allowed, before any other content except comments.
+  options: ParseOptions,
+  error: Option<ParseError>,
+  // After the input runs out in the middle of a token, it isn't tried again until the
+  // input is this long, so that a token split into many chunks isn't tokenized over and
+  // over.
+  retry_length: usize,
+}
+
+// An element along with the children parsed so far.
+struct OpenElement {
+  tag_name: String,
+  attributes: dom::AttrMap,
+  children: Vec<dom::Node>,
+}
+
+impl HtmlParser {
+  pub fn new() -> HtmlParser {
+    HtmlParser::with_options(ParseOptions::default())
   }
-  // Return the current character, and advance self.pos to the next character.
-  fn consume_char(&mut self) -> char {
-    let mut iterator = self.input[self.position..].char_indices(); // returns an iterator over the "char"s of a string slice, and their positions.
-    let (_, current_char) = iterator.next().unwrap();
-    let (next_position, _) = iterator.next().unwrap_or((1, ' '));
-    self.position += next_position;
 
-    return current_char;
+  // Like `new`, parsing with the given options.
+  pub fn with_options(options: ParseOptions) -> HtmlParser {
+    HtmlParser {
+      input: String::new(),
+      tokenizer: TokenizerState::default(),
+      open_elements: Vec::new(),
+      nodes: Vec::new(),
+      doctype_allowed: true,
+      options,
+      error: None,
+      retry_length: 0,
+    }
   }
-  // Consume characters until `test` returns false
-  // See to know usage of "where" clause: https://doc.rust-lang.org/rust-by-example/generics/where.html
-  fn consume_while<F>(&mut self, test: F) -> String
-  where
-    F: Fn(char) -> bool,
-  {
-    let mut result = String::new();
-    while !self.eof() && test(self.next_char()) {
-      result.push(self.consume_char());
+
+  // Add the next chunk of the document, and parse the tokens it completes.
+  pub fn feed(&mut self, chunk: &str) {
+    self.input.push_str(chunk);
+    if self.input.len() < self.retry_length || self.error.is_some() {
+      return;
     }
+    // A token is only taken once another one follows it, which shows that it has ended.
+    // Until then it might go on in the next chunk.
+    let (mut token, mut after) = self.next_token(&self.tokenizer);
+    while token != HtmlToken::Eof {
+      let (next, after_next) = self.next_token(&after);
+      if next == HtmlToken::Eof {
+        break;
+      }
+      let position = self.tokenizer.position;
+      self.tokenizer = after;
+      if let Err(error) = self.add_token(token, position) {
+        self.error = Some(error);
+        return;
+      }
+      token = next;
+      after = after_next;
+    }
+    let unparsed = self.input.len() - self.tokenizer.position;
+    self.retry_length = self.input.len() + unparsed;
+  }
 
-    return result;
+  // Parse the rest of the document and return a document node holding the top-level nodes.
+  pub fn finish(mut self) -> Result<Rc<dom::Node>, ParseError> {
+    if let Some(error) = self.error {
+      return Err(error);
+    }
+    loop {
+      let position = self.tokenizer.position;
+      let (token, after) = self.next_token(&self.tokenizer);
+      if token == HtmlToken::Eof {
+        break;
+      }
+      self.tokenizer = after;
+      self.add_token(token, position)?;
+    }
+    // The tokenizer stops in front of a tag cut off by the end of the input.
+    if self.tokenizer.position < self.input.len() || !self.open_elements.is_empty() {
+      return Err(self.error_at(HtmlError::UnexpectedEof, self.input.len()));
+    }
+    if self.options.collapse_whitespace {
+      collapse_whitespace(&mut self.nodes);
+    }
+    Ok(dom::document(self.nodes).into_rc())
   }
 
-  // Consume and discard zero or more whitespace characters.
-  fn consume_whitespace(&mut self) {
-    self.consume_while(char::is_whitespace);
+  // Read the token after `state`, and where the tokenizer is after it.
+  fn next_token(&self, state: &TokenizerState) -> (HtmlToken, TokenizerState) {
+    let mut tokenizer = Tokenizer::resume(&self.input, state.clone());
+    let token = tokenizer.next_token();
+    (token, tokenizer.state())
   }
 
-  // Parse a tag or attribute name.
-  fn parse_tag_name(&mut self) -> String {
-    self.consume_while(|c| match c {
-      'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-      _ => false,
-    })
+  // Add a token that starts at `position` in the input to the tree.
+  fn add_token(&mut self, token: HtmlToken, position: usize) -> Result<(), ParseError> {
+    match token {
+      HtmlToken::Doctype {
+        name,
+        public_id,
+        system_id,
+      } => {
+        // As in browsers, a misplaced doctype is ignored.
+        if self.doctype_allowed {
+          self.append(dom::doctype(name, public_id, system_id));
+        }
+        self.doctype_allowed = false;
+      }
+      HtmlToken::Comment(data) => self.append(dom::comment(data)),
+      HtmlToken::CharacterData(text) => {
+        let whitespace_only = text.chars().all(|c| c.is_ascii_whitespace());
+        // Whitespace between the top-level nodes is dropped, as it is in browsers.
+        if whitespace_only && self.open_elements.is_empty() {
+          return Ok(());
+        }
+        if !whitespace_only {
+          self.doctype_allowed = false;
+        }
+        self.append(dom::text(text));
+      }
+      HtmlToken::StartTag {
+        name,
+        attributes,
+        self_closing,
+      } => {
+        self.doctype_allowed = false;
+        // If an attribute is repeated, the tokenizer has kept only the first one.
+        let attributes = attributes.into_iter().collect();
+        // Self-closing tags (`<br/>`) and void elements (`<br>`) have no contents or end tag.
+        if self_closing || VOID_ELEMENTS.contains(&&*name) {
+          self.append(dom::elem(name, attributes, Vec::new()));
+        } else {
+          self.open_elements.push(OpenElement {
+            tag_name: name,
+            attributes,
+            children: Vec::new(),
+          });
+        }
+      }
+      // Markup like `<img src="a.png"></img>` closes a void element anyway, so that end tag
+      // is skipped.
+      HtmlToken::EndTag(name) if VOID_ELEMENTS.contains(&&*name) => {}
+      HtmlToken::EndTag(name) => match self.open_elements.pop() {
+        Some(mut element) if element.tag_name == name => {
+          let keep_whitespace = keeps_whitespace(&element.tag_name)
+            || self.open_elements.iter().any(|open| keeps_whitespace(&open.tag_name));
+          if self.options.collapse_whitespace && !keep_whitespace {
+            collapse_whitespace(&mut element.children);
+          }
+          self.append(dom::elem(element.tag_name, element.attributes, element.children));
+        }
+        Some(element) => {
+          let error = HtmlError::MismatchedTag {
+            expected: element.tag_name,
+            found: name,
+          };
+          return Err(self.error_at(error, position));
+        }
+        None => return Err(self.error_at(HtmlError::UnexpectedEndTag(name), position)),
+      },
+      HtmlToken::Eof => {}
+    }
+    Ok(())
   }
 
-  // Parse a single node.
-  fn parse_node(&mut self) -> dom::Node {
-    match self.next_char() {
-      '<' => self.parse_element(),
-      _ => self.parse_text(),
+  // Add a node to the innermost open element, or to the top level.
+  fn append(&mut self, node: dom::Node) {
+    match self.open_elements.last_mut() {
+      Some(element) => element.children.push(node),
+      None => self.nodes.push(node),
     }
   }
 
-  // Parse a text node.
-  fn parse_text(&mut self) -> dom::Node {
-    dom::text(self.consume_while(|c| c != '<'))
+  fn error_at(&self, error: HtmlError, position: usize) -> ParseError {
+    let (line, column) = location::line_column(&self.input, position);
+    ParseError {
+      error,
+      position,
+      line,
+      column,
+    }
+  }
+}
+
+impl Default for HtmlParser {
+  fn default() -> HtmlParser {
+    HtmlParser::new()
+  }
+}
+
+// Elements that never have children or a closing tag.
+// See the list here: https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+pub const VOID_ELEMENTS: &[&str] = &[
+  "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+  "track", "wbr",
+];
+
+// Elements whose contents are not parsed as markup, and don't have entities decoded.
+// See: https://html.spec.whatwg.org/multipage/syntax.html#raw-text-elements
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+// Errors that can occur while parsing an HTML document.
+#[derive(Debug, PartialEq)]
+pub enum HtmlError {
+  UnexpectedEof,
+  MismatchedTag { expected: String, found: String },
+  UnexpectedEndTag(String),
+}
+
+impl fmt::Display for HtmlError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      HtmlError::UnexpectedEof => write!(f, "unexpected end of input"),
+      HtmlError::MismatchedTag { expected, found } => write!(
+        f,
+        "expected closing tag </{}> but found </{}>",
+        expected, found
+      ),
+      HtmlError::UnexpectedEndTag(name) => write!(f, "unexpected closing tag </{}>", name),
+    }
   }
+}
 
-  // Parse a single element, including its open tag, contents, and closing tag.
-  fn parse_element(&mut self) -> dom::Node {
-    // Opening tag.
-    assert!(self.consume_char() == '<');
-    let tag_name = self.parse_tag_name();
-    let attrs = self.parse_attributes();
-    assert!(self.consume_char() == '>');
+impl std::error::Error for HtmlError {}
 
-    // Contents.
-    let children = self.parse_nodes();
+// An `HtmlError` along with where in the source it was found: the byte offset, and the
+// 1-based line and column.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+  pub error: HtmlError,
+  pub position: usize,
+  pub line: usize,
+  pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at line {}, column {}", self.error, self.line, self.column)
+  }
+}
 
-    // Closing tag.
-    assert!(self.consume_char() == '<');
-    assert!(self.consume_char() == '/');
-    assert!(self.parse_tag_name() == tag_name);
-    assert!(self.consume_char() == '>');
+impl std::error::Error for ParseError {}
 
-    return dom::elem(tag_name, attrs, children);
+// Replace character references such as `&amp;`, `&#60;` and `&#x3C;` with the characters they stand for.
+// Unrecognized references are left as they are.
+pub fn decode_entities(s: &str) -> String {
+  let mut result = String::new();
+  let mut rest = s;
+  while let Some(start) = rest.find('&') {
+    result.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let decoded = rest
+      .find(';')
+      .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+    match decoded {
+      Some((c, end)) => {
+        result.push(c);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        result.push('&');
+        rest = &rest[1..];
+      }
+    }
   }
+  result.push_str(rest);
+  result
+}
 
-  // Parse a single name="value" pair.
-  fn parse_attrs(&mut self) -> (String, String) {
-    let name = self.parse_tag_name();
-    assert!(self.consume_char() == '=');
-    let value = self.parse_attr_value();
+// Decode the name of a single character reference, without the surrounding `&` and `;`.
+fn decode_entity(name: &str) -> Option<char> {
+  match name {
+    "amp" => Some('&'),
+    "lt" => Some('<'),
+    "gt" => Some('>'),
+    "quot" => Some('"'),
+    "apos" => Some('\''),
+    _ if name.starts_with("#x") || name.starts_with("#X") => decode_numeric_entity(&name[2..], 16),
+    _ if name.starts_with('#') => decode_numeric_entity(&name[1..], 10),
+    _ => None,
+  }
+}
 
-    return (name, value);
+// Decode the digits of a numeric character reference such as `&#169;` or `&#xA9;`.
+// As in browsers, zero, surrogates and numbers past the last code point stand for the
+// replacement character U+FFFD.
+// See: https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+fn decode_numeric_entity(digits: &str, radix: u32) -> Option<char> {
+  // `from_str_radix` would also accept a leading `+`, which is not valid here.
+  if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+    return None;
+  }
+  // A number too large for a `u32` is past the last code point too.
+  let code = u32::from_str_radix(digits, radix).unwrap_or(u32::MAX);
+  match char::from_u32(code) {
+    Some(c) if c != '\0' => Some(c),
+    _ => Some('\u{fffd}'),
   }
+}
 
-  // Parse a quoted value.
-  fn parse_attr_value(&mut self) -> String {
-    let quote = self.consume_char();
-    assert!(quote == '"' || quote == '/');
-    let value = self.consume_while(|c| c != quote);
-    assert!(self.consume_char() == quote);
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-    return value;
+  #[test]
+  fn decodes_invalid_numeric_references_as_the_replacement_character() {
+    assert_eq!(decode_entities("&#0;"), "\u{fffd}");
+    assert_eq!(decode_entities("&#xD800;"), "\u{fffd}");
+    assert_eq!(decode_entities("&#x110000;"), "\u{fffd}");
+    assert_eq!(decode_entities("&#99999999999;"), "\u{fffd}");
+    assert_eq!(decode_entities("&#x41;&#169;"), "A\u{a9}");
+    assert_eq!(decode_entities("&#x;&#+1;"), "&#x;&#+1;");
   }
 
-  // Parse a list of name="value" pairs, separated by whitespace.
-  fn parse_attributes(&mut self) -> dom::AttrMap {
-    let mut attributes = HashMap::new();
-    loop {
-      self.consume_whitespace();
-      if self.next_char() == '>' {
-        break;
-      }
-      let (name, value) = self.parse_attrs();
-      attributes.insert(name, value);
+  #[test]
+  fn multibyte_char_at_the_end_of_the_input() {
+    let root = parse("<p>a</p>\u{65e5}\u{672c}".to_string()).unwrap();
+    assert_eq!(root.text_content(), "a\u{65e5}\u{672c}");
+    assert!(parse("<p>\u{e9}".to_string()).is_err());
+    assert!(parse("<p title=\"\u{e9}\">".to_string()).is_err());
+  }
+
+  #[test]
+  fn truncated_input_is_an_error() {
+    assert!(parse("<div".to_string()).is_err());
+    assert!(parse("<div class=\"a".to_string()).is_err());
+
+    // Every prefix of a document parses or fails without panicking.
+    let source = "<!DOCTYPE html><div id=a class='b'><!-- c --><p>d &amp; e</p></div>";
+    for (i, _) in source.char_indices() {
+      let _ = parse(source[..i].to_string());
     }
+  }
 
-    return attributes;
+  #[test]
+  fn parse_error_on_line_3_is_reported_on_line_3() {
+    let error = parse("<div>\n  <p>a</p>\n  </span>\n</div>".to_string()).unwrap_err();
+    assert_eq!(error.line, 3);
   }
 
-  // Parse a sequence of sibling nodes.
-  fn parse_nodes(&mut self) -> Vec<dom::Node> {
-    let mut nodes = Vec::new();
-    loop {
-      self.consume_whitespace();
-      if self.eof() | self.starts_with("</") {
-        break;
-      }
-      nodes.push(self.parse_node());
+  #[test]
+  fn contents_of_raw_text_and_rcdata_elements_are_text() {
+    let html = "<title>a &lt;b&gt;</title><script>c</b> &lt;</script><style/><p>d</p>";
+    let root = parse(html.to_string()).unwrap();
+    let texts: Vec<String> = root.children.iter().map(|child| child.text_content()).collect();
+    assert_eq!(texts, ["a <b>", "c</b> &lt;", "", "d"]);
+  }
+
+  #[test]
+  fn end_tag_without_a_start_tag_is_an_error() {
+    let error = parse("<p>a</p></div>".to_string()).unwrap_err();
+    assert_eq!(error.error, HtmlError::UnexpectedEndTag("div".to_string()));
+    assert_eq!(error.position, 8);
+  }
+
+  #[test]
+  fn feeding_a_document_split_anywhere_gives_the_same_tree() {
+    let html = "<!DOCTYPE html><!-- a --><html><body id=b><p>c &amp; d <b>e</b></p>\
+                <script>f</b> < g</script><title>h</title><br></br>\u{e9}</body></html>";
+    let whole = parse(html.to_string()).unwrap().to_html();
+    for (i, _) in html.char_indices() {
+      let mut parser = HtmlParser::new();
+      parser.feed(&html[..i]);
+      parser.feed(&html[i..]);
+      assert_eq!(parser.finish().unwrap().to_html(), whole, "split at {}", i);
     }
+    let mut parser = HtmlParser::new();
+    for (i, c) in html.char_indices() {
+      parser.feed(&html[i..i + c.len_utf8()]);
+    }
+    assert_eq!(parser.finish().unwrap().to_html(), whole);
+
+    let html = "<div><p>a</p></span></div>";
+    let whole = parse(html.to_string()).unwrap_err();
+    for (i, _) in html.char_indices() {
+      let mut parser = HtmlParser::new();
+      parser.feed(&html[..i]);
+      parser.feed(&html[i..]);
+      assert_eq!(parser.finish().unwrap_err(), whole, "split at {}", i);
+    }
+  }
+
+  #[test]
+  fn whitespace_is_only_collapsed_when_asked_to() {
+    let collapse = ParseOptions {
+      collapse_whitespace: true,
+    };
+    let html = "\n<p>  a   b  </p>\n";
+    assert_eq!(parse(html.to_string()).unwrap().text_content(), "  a   b  ");
+    let root = parse_with_options(html.to_string(), collapse).unwrap();
+    assert_eq!(root.text_content(), "a b");
+
+    // Whitespace-only text between elements is kept as a space.
+    let html = "<p>\n  <b>a</b>\n  <i>b</i>\n</p>";
+    let root = parse_with_options(html.to_string(), collapse).unwrap();
+    assert_eq!(root.children[0].children.len(), 3);
+    assert_eq!(root.text_content(), "a b");
 
-    return nodes;
+    // Whitespace in `<pre>` is kept.
+    let html = "<div> <pre>  a\n <b> b </b> </pre> </div>";
+    let root = parse_with_options(html.to_string(), collapse).unwrap();
+    assert_eq!(root.text_content(), "  a\n  b  ");
   }
 }
@@ -0,0 +1,409 @@
+use super::html_parser::{decode_entities, RAW_TEXT_ELEMENTS};
+
+// Splitting HTML source into tokens, as the first step of parsing.
+// See: https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+
+// Elements whose contents are text with entities decoded, but no tags.
+// See: https://html.spec.whatwg.org/multipage/syntax.html#escapable-raw-text-elements
+pub const RCDATA_ELEMENTS: &[&str] = &["textarea", "title"];
+
+// Tag and attribute names are in lowercase, and entities are decoded in text and
+// attribute values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtmlToken {
+  Doctype {
+    name: String,
+    public_id: Option<String>,
+    system_id: Option<String>,
+  },
+  StartTag {
+    name: String,
+    // In source order. If an attribute is repeated, only the first one is kept.
+    attributes: Vec<(String, String)>,
+    self_closing: bool, // `<br/>`
+  },
+  EndTag(String),
+  Comment(String),
+  CharacterData(String),
+  Eof,
+}
+
+// Split a document into tokens lazily. The last token is always `Eof`.
+// Tokenizing never fails: as in browsers, markup that isn't valid is read as text or
+// as a comment, and a tag cut off by the end of the input is dropped.
+pub fn tokenize(source: &str) -> impl Iterator<Item = HtmlToken> + '_ {
+  Tokenizer::resume(source, TokenizerState::default())
+}
+
+// Where a tokenizer has got to in its input, so that it can go on from there once more of
+// the document has arrived, as `HtmlParser::feed` does.
+#[derive(Debug, Clone, Default)]
+pub struct TokenizerState {
+  pub position: usize,
+  // Set after the start tag of a raw text or RCDATA element, whose contents run up to
+  // its end tag. The flag tells whether entities are decoded, as they are in RCDATA.
+  text_end_tag: Option<(String, bool)>,
+}
+
+pub struct Tokenizer<'a> {
+  position: usize,
+  input: &'a str,
+  text_end_tag: Option<(String, bool)>,
+  done: bool,
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+  type Item = HtmlToken;
+
+  fn next(&mut self) -> Option<HtmlToken> {
+    if self.done {
+      return None;
+    }
+    let token = self.next_token();
+    self.done = token == HtmlToken::Eof;
+    Some(token)
+  }
+}
+
+impl<'a> Tokenizer<'a> {
+  // Go on tokenizing `input` from `state`.
+  pub fn resume(input: &'a str, state: TokenizerState) -> Tokenizer<'a> {
+    Tokenizer {
+      position: state.position,
+      input,
+      text_end_tag: state.text_end_tag,
+      done: false,
+    }
+  }
+
+  // Where the tokenizer has got to, to resume from.
+  pub fn state(&self) -> TokenizerState {
+    TokenizerState {
+      position: self.position,
+      text_end_tag: self.text_end_tag.clone(),
+    }
+  }
+
+  // Read the current character without consuming it, or `None` at the end of the input.
+  fn next_char(&self) -> Option<char> {
+    self.input[self.position..].chars().next()
+  }
+
+  // Read the character `n` characters after the current one.
+  fn nth_char(&self, n: usize) -> Option<char> {
+    self.input[self.position..].chars().nth(n)
+  }
+
+  // Do the next characters start with the given string?
+  fn starts_with(&self, s: &str) -> bool {
+    self.input[self.position..].starts_with(s)
+  }
+
+  // Like `starts_with`, but ignoring ASCII case.
+  fn starts_with_ignore_case(&self, s: &str) -> bool {
+    match self.input[self.position..].get(..s.len()) {
+      Some(prefix) => prefix.eq_ignore_ascii_case(s),
+      None => false,
+    }
+  }
+
+  // Return true if all input is consumed.
+  // `position` is a byte offset that only ever lands on a character boundary, so this is
+  // true exactly when there are no characters left, however many bytes the last one took.
+  fn eof(&self) -> bool {
+    self.position >= self.input.len()
+  }
+
+  // Return the current character, and advance past all of its bytes.
+  fn consume_char(&mut self) -> Option<char> {
+    let c = self.next_char()?;
+    self.position += c.len_utf8();
+    Some(c)
+  }
+
+  // Consume characters until `test` returns false
+  fn consume_while<F>(&mut self, test: F) -> &'a str
+  where
+    F: Fn(char) -> bool,
+  {
+    let start = self.position;
+    while self.next_char().is_some_and(&test) {
+      self.consume_char();
+    }
+    &self.input[start..self.position]
+  }
+
+  // Consume everything up to and including `end`, or the rest of the input if `end`
+  // isn't found, returning what came before it.
+  fn consume_until(&mut self, end: &str) -> &'a str {
+    let rest = &self.input[self.position..];
+    match rest.find(end) {
+      Some(index) => {
+        self.position += index + end.len();
+        &rest[..index]
+      }
+      None => {
+        self.position = self.input.len();
+        rest
+      }
+    }
+  }
+
+  // Read the next token. Once the input has run out, this is `Eof`.
+  pub fn next_token(&mut self) -> HtmlToken {
+    if let Some((tag_name, decode)) = self.text_end_tag.take() {
+      let text = self.consume_text_until_end_tag(&tag_name);
+      if !text.is_empty() {
+        let text = if decode { decode_entities(text) } else { text.to_string() };
+        return HtmlToken::CharacterData(text);
+      }
+    }
+    if self.eof() {
+      return HtmlToken::Eof;
+    }
+    if self.starts_with("<!--") {
+      self.position += "<!--".len();
+      return HtmlToken::Comment(self.consume_until("-->").to_string());
+    }
+    if self.starts_with_ignore_case("<!DOCTYPE") {
+      return self.consume_doctype();
+    }
+    if self.starts_with("</") && self.nth_char(2).is_some_and(|c| c.is_ascii_alphabetic()) {
+      return self.consume_end_tag();
+    }
+    if self.starts_with_tag() {
+      return self.consume_start_tag();
+    }
+    // Other markup starting with `<!`, `<?` or `</` is read as a comment, except for `</>`,
+    // which is dropped.
+    if self.starts_with("</>") {
+      self.position += "</>".len();
+      return self.next_token();
+    }
+    if self.starts_with("<!") || self.starts_with("</") {
+      self.position += "<!".len();
+      return HtmlToken::Comment(self.consume_until(">").to_string());
+    }
+    if self.starts_with("<?") {
+      self.position += "<".len();
+      return HtmlToken::Comment(self.consume_until(">").to_string());
+    }
+    self.consume_character_data()
+  }
+
+  // Text up to the next markup. A `<` that doesn't start markup is part of the text.
+  fn consume_character_data(&mut self) -> HtmlToken {
+    let start = self.position;
+    self.consume_char();
+    while let Some(end) = self.input[self.position..].find('<') {
+      self.position += end;
+      if matches!(self.nth_char(1), Some('!' | '?' | '/')) || self.starts_with_tag() {
+        return HtmlToken::CharacterData(decode_entities(&self.input[start..self.position]));
+      }
+      self.consume_char();
+    }
+    self.position = self.input.len();
+    HtmlToken::CharacterData(decode_entities(&self.input[start..]))
+  }
+
+  // Do the next characters start a start tag, e.g. `<p`?
+  fn starts_with_tag(&self) -> bool {
+    self.starts_with("<") && self.nth_char(1).is_some_and(|c| c.is_ascii_alphabetic())
+  }
+
+  // The contents of a raw text or RCDATA element, up to its end tag.
+  fn consume_text_until_end_tag(&mut self, tag_name: &str) -> &'a str {
+    let rest = &self.input[self.position..];
+    let end = find_end_tag(rest, tag_name).unwrap_or(rest.len());
+    self.position += end;
+    &rest[..end]
+  }
+
+  // A tag name, in lowercase.
+  fn consume_tag_name(&mut self) -> String {
+    self
+      .consume_while(|c| !c.is_whitespace() && c != '/' && c != '>')
+      .to_ascii_lowercase()
+  }
+
+  // `<name attr="value" ...>` or `<name ... />`
+  // A start tag cut off by the end of the input is left where it is, and ends the input.
+  fn consume_start_tag(&mut self) -> HtmlToken {
+    let start = self.position;
+    self.consume_char();
+    let name = self.consume_tag_name();
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let self_closing = loop {
+      self.consume_while(|c| c.is_whitespace());
+      if self.starts_with("/>") {
+        self.position += "/>".len();
+        break true;
+      }
+      match self.next_char() {
+        None => {
+          self.position = start;
+          return HtmlToken::Eof;
+        }
+        Some('>') => {
+          self.consume_char();
+          break false;
+        }
+        Some('/') => {
+          self.consume_char();
+        }
+        Some(_) => {
+          let (attribute, value) = self.consume_attribute();
+          if !attributes.iter().any(|(name, _)| *name == attribute) {
+            attributes.push((attribute, value));
+          }
+        }
+      }
+    };
+    // `<script/>` has no contents, so no text follows it.
+    if !self_closing && RAW_TEXT_ELEMENTS.contains(&&*name) {
+      self.text_end_tag = Some((name.clone(), false));
+    } else if !self_closing && RCDATA_ELEMENTS.contains(&&*name) {
+      self.text_end_tag = Some((name.clone(), true));
+    }
+    HtmlToken::StartTag {
+      name,
+      attributes,
+      self_closing,
+    }
+  }
+
+  // `name`, `name=value`, `name="value"` or `name='value'`.
+  fn consume_attribute(&mut self) -> (String, String) {
+    let name_start = self.position;
+    // A name can start with `=`, but can't contain one after that.
+    self.consume_char();
+    self.consume_while(|c| !c.is_whitespace() && !matches!(c, '/' | '>' | '='));
+    let name = self.input[name_start..self.position].to_ascii_lowercase();
+    self.consume_while(|c| c.is_whitespace());
+    if !self.starts_with("=") {
+      return (name, String::new());
+    }
+    self.consume_char();
+    self.consume_while(|c| c.is_whitespace());
+    let value = match self.next_char() {
+      Some(quote @ ('"' | '\'')) => {
+        self.consume_char();
+        self.consume_until(&quote.to_string())
+      }
+      _ => self.consume_while(|c| !c.is_whitespace() && c != '>'),
+    };
+    (name, decode_entities(value))
+  }
+
+  // `</name>`. Anything after the name is ignored.
+  fn consume_end_tag(&mut self) -> HtmlToken {
+    self.position += "</".len();
+    let name = self.consume_tag_name();
+    self.consume_until(">");
+    HtmlToken::EndTag(name)
+  }
+
+  // `<!DOCTYPE name>`, optionally followed by `PUBLIC "public id" "system id"` or
+  // `SYSTEM "system id"`.
+  fn consume_doctype(&mut self) -> HtmlToken {
+    self.position += "<!DOCTYPE".len();
+    self.consume_while(|c| c.is_whitespace());
+    let name = self
+      .consume_while(|c| !c.is_whitespace() && c != '>')
+      .to_ascii_lowercase();
+    self.consume_while(|c| c.is_whitespace());
+    let keyword = self.consume_while(|c| c.is_ascii_alphabetic());
+    let mut public_id = None;
+    let mut system_id = None;
+    if keyword.eq_ignore_ascii_case("PUBLIC") {
+      public_id = self.consume_doctype_identifier();
+      system_id = self.consume_doctype_identifier();
+    } else if keyword.eq_ignore_ascii_case("SYSTEM") {
+      system_id = self.consume_doctype_identifier();
+    }
+    self.consume_until(">");
+    HtmlToken::Doctype {
+      name,
+      public_id,
+      system_id,
+    }
+  }
+
+  // A quoted identifier in a doctype, or `None` if there isn't one.
+  fn consume_doctype_identifier(&mut self) -> Option<String> {
+    self.consume_while(|c| c.is_whitespace());
+    match self.next_char() {
+      Some(quote @ ('"' | '\'')) => {
+        self.consume_char();
+        let end = self.input[self.position..].find(&[quote, '>'][..]);
+        let end = end.map_or(self.input.len(), |end| self.position + end);
+        let identifier = self.input[self.position..end].to_string();
+        self.position = end;
+        if self.starts_with(&quote.to_string()) {
+          self.consume_char();
+        }
+        Some(identifier)
+      }
+      _ => None,
+    }
+  }
+}
+
+// Find where `</tag_name` first appears in `text`, ignoring case, followed by `>`, `/`,
+// whitespace or the end of the text, so `</scripts` doesn't end a script.
+// Only the text after each `</` is compared, since lowercasing the rest of the document
+// for every script or style element would make parsing quadratic.
+// See: https://html.spec.whatwg.org/multipage/parsing.html#script-data-end-tag-name-state
+fn find_end_tag(text: &str, tag_name: &str) -> Option<usize> {
+  let mut start = 0;
+  while let Some(index) = text[start..].find("</") {
+    let index = start + index;
+    let after = &text[index + "</".len()..];
+    let is_end_tag = after
+      .get(..tag_name.len())
+      .is_some_and(|name| name.eq_ignore_ascii_case(tag_name))
+      && after[tag_name.len()..]
+        .chars()
+        .next()
+        .is_none_or(|c| c == '>' || c == '/' || c.is_ascii_whitespace());
+    if is_end_tag {
+      return Some(index);
+    }
+    start = index + "</".len();
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn eof_after_consuming_a_final_multibyte_char() {
+    let mut tokenizer = Tokenizer::resume("a\u{e9}", TokenizerState::default());
+    assert_eq!(tokenizer.consume_char(), Some('a'));
+    assert!(!tokenizer.eof());
+    assert_eq!(tokenizer.consume_char(), Some('\u{e9}'));
+    assert!(tokenizer.eof());
+    assert_eq!(tokenizer.position, tokenizer.input.len());
+    assert_eq!(tokenizer.consume_char(), None);
+  }
+
+  #[test]
+  fn end_tag_name_must_be_followed_by_a_delimiter() {
+    assert_eq!(find_end_tag("a</scripts></script>", "script"), Some(11));
+    assert_eq!(find_end_tag("a</styleX></STYLE >", "style"), Some(10));
+    assert_eq!(find_end_tag("a</style/>", "style"), Some(1));
+    assert_eq!(find_end_tag("a</style", "style"), Some(1));
+    assert_eq!(find_end_tag("a</styleX", "style"), None);
+    assert_eq!(find_end_tag("a</scrip\u{e9}", "script"), None);
+  }
+
+  #[test]
+  fn cut_off_start_tag_is_left_in_place() {
+    let mut tokenizer = Tokenizer::resume("a<p class=b", TokenizerState::default());
+    assert_eq!(tokenizer.next_token(), HtmlToken::CharacterData("a".to_string()));
+    assert_eq!(tokenizer.next_token(), HtmlToken::Eof);
+    assert_eq!(tokenizer.state().position, 1);
+  }
+}
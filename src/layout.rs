@@ -0,0 +1,1329 @@
+use super::css_parser::{BorderStyle, Unit, Value, DEFAULT_FONT_SIZE};
+use super::dom::NodeType;
+use super::style::{Clear, Display, Float, Position, PropertyMap, StyledNode};
+
+// CSS box model. All sizes are in px.
+// See: https://www.w3.org/TR/CSS2/box.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dimensions {
+  // Position of the content area relative to the document origin.
+  pub content: Rect,
+  // Surrounding edges.
+  pub padding: EdgeSizes,
+  pub border: EdgeSizes,
+  pub margin: EdgeSizes,
+}
+
+impl Dimensions {
+  // The area covered by the content area and its padding.
+  pub fn padding_box(self) -> Rect {
+    self.content.expanded_by(self.padding)
+  }
+
+  // The area covered by the content area, padding and borders.
+  pub fn border_box(self) -> Rect {
+    self.padding_box().expanded_by(self.border)
+  }
+
+  // The area covered by the content area, padding, borders and margins.
+  pub fn margin_box(self) -> Rect {
+    self.border_box().expanded_by(self.margin)
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rect {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+}
+
+impl Rect {
+  // The rectangle grown outwards by `edge` on each side.
+  pub fn expanded_by(self, edge: EdgeSizes) -> Rect {
+    Rect {
+      x: self.x - edge.left,
+      y: self.y - edge.top,
+      width: self.width + edge.left + edge.right,
+      height: self.height + edge.top + edge.bottom,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EdgeSizes {
+  pub top: f32,
+  pub right: f32,
+  pub bottom: f32,
+  pub left: f32,
+}
+
+// A node in the layout tree.
+#[derive(Debug)]
+pub struct LayoutBox<'a> {
+  pub dimensions: Dimensions,
+  pub box_type: BoxType<'a>,
+  pub children: Vec<LayoutBox<'a>>,
+  // The lines that the inline content of an anonymous block is laid out in.
+  pub lines: Vec<LineBox<'a>>,
+}
+
+#[derive(Debug)]
+pub enum BoxType<'a> {
+  BlockNode(&'a StyledNode<'a>),
+  InlineNode(&'a StyledNode<'a>),
+  // Placed like an inline box, but holds its children like a block box.
+  InlineBlockNode(&'a StyledNode<'a>),
+  // A block that wraps a run of inline boxes among block boxes.
+  // See: https://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+  AnonymousBlock,
+}
+
+// A line of inline content, see `LayoutBox::layout_inline`.
+#[derive(Debug)]
+pub struct LineBox<'a> {
+  pub items: Vec<InlineItem<'a>>,
+  pub height: f32,
+}
+
+// A piece of a line: a run of words from one text node, or an inline-block.
+#[derive(Debug)]
+pub struct InlineItem<'a> {
+  // The position and size of the item, like the content area in `Dimensions`.
+  pub rect: Rect,
+  pub styled: &'a StyledNode<'a>,
+  // The words of the run separated by single spaces, or `None` for an inline-block.
+  pub text: Option<String>,
+}
+
+// Measures text, so that inline layout knows where to wrap lines.
+pub trait TextMeasurer {
+  // The width of `text` in px, set in a font of the given size in px.
+  fn text_width(&self, text: &str, font_size: f32) -> f32;
+}
+
+// Takes each character to be half as wide as the font size, which is about the average
+// in common fonts. For when there is no actual font to measure text with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CharacterCountMeasurer;
+
+impl TextMeasurer for CharacterCountMeasurer {
+  fn text_width(&self, text: &str, font_size: f32) -> f32 {
+    text.chars().count() as f32 * font_size / 2.0
+  }
+}
+
+// The height of a line of text, as a multiple of the font size. This is about what
+// browsers use for `line-height: normal`.
+const LINE_HEIGHT: f32 = 1.2;
+
+// The floats placed so far in a block formatting context, whose line boxes are shortened
+// to make room for them. Rects are the floats' margin boxes.
+// See: https://www.w3.org/TR/CSS2/visuren.html#block-formatting
+#[derive(Debug, Default)]
+struct FloatContext {
+  floats: Vec<(Float, Rect)>,
+}
+
+impl FloatContext {
+  // The part of `area` between the left and right floats beside the band from `y` down
+  // to `y + height`, as its left and right edges.
+  fn available(&self, y: f32, height: f32, area: Rect) -> (f32, f32) {
+    let (mut left, mut right) = (area.x, area.x + area.width);
+    for (float, rect) in self.beside(y, height) {
+      match float {
+        Float::Left => left = left.max(rect.x + rect.width),
+        _ => right = right.min(rect.x),
+      }
+    }
+    (left, right)
+  }
+
+  // Where the first of the floats beside the band from `y` down to `y + height` ends, or
+  // `None` if there aren't any.
+  fn next_bottom(&self, y: f32, height: f32) -> Option<f32> {
+    self
+      .beside(y, height)
+      .map(|(_, rect)| rect.y + rect.height)
+      .reduce(f32::min)
+  }
+
+  // Overlaps of less than a hundredth of a px are left out, since moving boxes around
+  // leaves rounding errors about that large.
+  fn beside(&self, y: f32, height: f32) -> impl Iterator<Item = &(Float, Rect)> {
+    const TOLERANCE: f32 = 0.01;
+    self.floats.iter().filter(move |(_, rect)| {
+      rect.y + TOLERANCE < y + height && y + TOLERANCE < rect.y + rect.height
+    })
+  }
+
+  // The bottom of the lowest of the first `count` floats that `clear` moves a box below,
+  // or `None` if there aren't any.
+  fn clearance(&self, clear: Clear, count: usize) -> Option<f32> {
+    self.floats[..count]
+      .iter()
+      .filter(|(float, _)| match clear {
+        Clear::None => false,
+        Clear::Left => *float == Float::Left,
+        Clear::Right => *float == Float::Right,
+        Clear::Both => true,
+      })
+      .map(|(_, rect)| rect.y + rect.height)
+      .reduce(f32::max)
+  }
+
+  // The bottom of the lowest float, or `None` if there aren't any.
+  fn bottom(&self) -> Option<f32> {
+    self.clearance(Clear::Both, self.floats.len())
+  }
+
+  // Move the floats from index `first` on down by `dy`, along with the box they are in.
+  fn translate(&mut self, first: usize, dy: f32) {
+    for (_, rect) in &mut self.floats[first..] {
+      rect.y += dy;
+    }
+  }
+}
+
+// Lay out a styled tree inside `containing_block`, measuring text with
+// `CharacterCountMeasurer`. The root is always laid out as a block.
+// The containing block's height is where the root is placed, and is usually zero.
+pub fn layout<'a>(styled: &'a StyledNode<'a>, containing_block: Dimensions) -> LayoutBox<'a> {
+  layout_with_measurer(styled, containing_block, &CharacterCountMeasurer)
+}
+
+// Like `layout`, measuring text with `measurer`.
+pub fn layout_with_measurer<'a>(
+  styled: &'a StyledNode<'a>,
+  containing_block: Dimensions,
+  measurer: &dyn TextMeasurer,
+) -> LayoutBox<'a> {
+  let mut root = build_box(styled, BoxType::BlockNode(styled));
+  root.layout(containing_block, &mut FloatContext::default(), measurer);
+  // Boxes positioned in the root, and fixed boxes, are placed in its padding box, which
+  // stands in for the viewport.
+  let initial_containing_block = root.dimensions.padding_box();
+  root.layout_positioned(initial_containing_block, initial_containing_block, measurer);
+  root
+}
+
+// Build the tree of boxes for `styled` with the type its `display` gives it, without
+// computing any dimensions yet. Nodes with `display: none` are left out along with their
+// descendants, and runs of inline boxes among blocks are wrapped in anonymous blocks.
+pub fn build_layout_tree<'a>(styled: &'a StyledNode<'a>) -> LayoutBox<'a> {
+  let box_type = match styled.display() {
+    Display::Block => BoxType::BlockNode(styled),
+    Display::InlineBlock => BoxType::InlineBlockNode(styled),
+    Display::Inline | Display::None => BoxType::InlineNode(styled),
+  };
+  build_box(styled, box_type)
+}
+
+// Build a box of the given type for `styled`, along with the boxes of its children.
+fn build_box<'a>(styled: &'a StyledNode<'a>, box_type: BoxType<'a>) -> LayoutBox<'a> {
+  let mut root = LayoutBox::new(box_type);
+  for child in &styled.children {
+    match child.display() {
+      Display::None => {}
+      // Floats and absolutely positioned boxes are blocks. They are out of the flow, so they
+      // don't split the run of inline boxes that they are in.
+      _ if matches!(child.position(), Position::Absolute | Position::Fixed)
+        || child.float() != Float::None =>
+      {
+        let out_of_flow = build_box(child, BoxType::BlockNode(child));
+        match root.children.last_mut() {
+          Some(
+            last @ LayoutBox {
+              box_type: BoxType::AnonymousBlock,
+              ..
+            },
+          ) => last.children.push(out_of_flow),
+          _ => root.children.push(out_of_flow),
+        }
+      }
+      Display::Block => root.children.push(build_layout_tree(child)),
+      // Whitespace-only text doesn't start a run of inline boxes, so the whitespace between
+      // blocks is left out.
+      // See: https://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+      Display::Inline if is_whitespace_only(child) && !root.ends_with_inline_content() => {}
+      Display::Inline | Display::InlineBlock => root
+        .inline_container()
+        .children
+        .push(build_layout_tree(child)),
+    }
+  }
+  root
+}
+
+// Is `styled` a text node with nothing but whitespace in it?
+fn is_whitespace_only(styled: &StyledNode) -> bool {
+  match styled.node.node_type {
+    NodeType::Text(ref text) => text.chars().all(|c| c.is_ascii_whitespace()),
+    _ => false,
+  }
+}
+
+impl<'a> LayoutBox<'a> {
+  fn new(box_type: BoxType<'a>) -> LayoutBox<'a> {
+    LayoutBox {
+      dimensions: Dimensions::default(),
+      box_type,
+      children: Vec::new(),
+      lines: Vec::new(),
+    }
+  }
+
+  // The specified values of the styled node, or `None` for an anonymous block.
+  pub fn specified_values(&self) -> Option<&PropertyMap> {
+    match self.box_type {
+      BoxType::BlockNode(styled)
+      | BoxType::InlineNode(styled)
+      | BoxType::InlineBlockNode(styled) => Some(&styled.specified_values),
+      BoxType::AnonymousBlock => None,
+    }
+  }
+
+  // Is the last child inline content, so that an inline-level child would go next to it?
+  fn ends_with_inline_content(&self) -> bool {
+    match self.box_type {
+      BoxType::InlineNode(_) | BoxType::AnonymousBlock => true,
+      BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => matches!(
+        self.children.last(),
+        Some(LayoutBox {
+          box_type: BoxType::AnonymousBlock,
+          ..
+        })
+      ),
+    }
+  }
+
+  // Where a new inline-level child should go. A block container can't hold inline boxes
+  // next to block boxes, so they go into an anonymous block, reusing the last one if possible.
+  fn inline_container(&mut self) -> &mut LayoutBox<'a> {
+    match self.box_type {
+      BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
+      BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => {
+        if !self.ends_with_inline_content() {
+          self.children.push(LayoutBox::new(BoxType::AnonymousBlock));
+        }
+        self.children.last_mut().unwrap()
+      }
+    }
+  }
+
+  // Lay out a box and its descendants.
+  // Inline-level boxes are laid out by the block that holds them, see `layout_inline`.
+  // `floats` are the floats around the box, in the block formatting context it is in.
+  fn layout(
+    &mut self,
+    containing_block: Dimensions,
+    floats: &mut FloatContext,
+    measurer: &dyn TextMeasurer,
+  ) {
+    match self.box_type {
+      BoxType::BlockNode(_) | BoxType::AnonymousBlock => {
+        self.layout_block(containing_block, floats, measurer)
+      }
+      BoxType::InlineNode(_) | BoxType::InlineBlockNode(_) => {}
+    }
+  }
+
+  // Lay out a block-level box and its descendants.
+  // See: https://www.w3.org/TR/CSS2/visudet.html#blockwidth
+  fn layout_block(
+    &mut self,
+    containing_block: Dimensions,
+    floats: &mut FloatContext,
+    measurer: &dyn TextMeasurer,
+  ) {
+    // The width depends on the containing block, so it is computed first.
+    self.calculate_block_width(containing_block);
+    // Then the box is placed below the boxes that come before it.
+    self.calculate_block_position(containing_block);
+    // Its children are laid out inside it, which gives its height.
+    // Only anonymous blocks hold inline-level boxes, see `inline_container`.
+    self.dimensions.content.height = 0.0;
+    let mut own_floats = FloatContext::default();
+    let establishes_context = self.establishes_formatting_context();
+    let floats = if establishes_context { &mut own_floats } else { floats };
+    match self.box_type {
+      BoxType::AnonymousBlock => self.layout_inline(floats, measurer),
+      _ => self.layout_block_children(floats, measurer),
+    }
+    // A box whose floats are its own grows to hold them.
+    // See: https://www.w3.org/TR/CSS2/visudet.html#root-height
+    if let Some(bottom) = floats.bottom().filter(|_| establishes_context) {
+      let content = &mut self.dimensions.content;
+      content.height = content.height.max(bottom - content.y);
+    }
+    self.calculate_block_height();
+  }
+
+  // Compute the width of the box and its horizontal edges, so that the margin box fills
+  // the containing block. An `auto` width takes up the space that is left, and `auto`
+  // margins share it if the width is set, which centers the box.
+  // See: https://www.w3.org/TR/CSS2/visudet.html#blockwidth
+  fn calculate_block_width(&mut self, containing_block: Dimensions) {
+    let containing_width = containing_block.content.width;
+    // `None` stands for `auto`.
+    let width = match self.specified_values().and_then(|values| values.get("width")) {
+      Some(value @ (Value::Length(..) | Value::Calc(_))) => {
+        Some(value.to_px(containing_width, self.font_size()))
+      }
+      _ => None,
+    };
+    let mut margin_left = self.margin("margin-left", containing_block);
+    let mut margin_right = self.margin("margin-right", containing_block);
+    let border_left = self.border_width("left");
+    let border_right = self.border_width("right");
+    let padding_left = self.length("padding-left", containing_block);
+    let padding_right = self.length("padding-right", containing_block);
+
+    let total = width.unwrap_or(0.0)
+      + margin_left.unwrap_or(0.0)
+      + margin_right.unwrap_or(0.0)
+      + border_left
+      + border_right
+      + padding_left
+      + padding_right;
+    // A box that is already too wide has nothing to share, so `auto` margins are zero.
+    if width.is_some() && total > containing_width {
+      margin_left = margin_left.or(Some(0.0));
+      margin_right = margin_right.or(Some(0.0));
+    }
+    let underflow = containing_width - total;
+    let (width, margin_left, margin_right) = match (width, margin_left, margin_right) {
+      // Inline-blocks, floats and absolutely positioned boxes don't stretch their margins
+      // to fill the containing block, and `auto` margins are zero.
+      // See: https://www.w3.org/TR/CSS2/visudet.html#inlineblock-width
+      (width, left, right)
+        if matches!(self.box_type, BoxType::InlineBlockNode(_))
+          || self.float() != Float::None
+          || self.is_absolutely_positioned() =>
+      {
+        (
+          width.unwrap_or(underflow.max(0.0)),
+          left.unwrap_or(0.0),
+          right.unwrap_or(0.0),
+        )
+      }
+      // If nothing is `auto`, the right margin gives way.
+      (Some(width), Some(left), Some(right)) => (width, left, right + underflow),
+      (Some(width), Some(left), None) => (width, left, underflow),
+      (Some(width), None, Some(right)) => (width, underflow, right),
+      (Some(width), None, None) => (width, underflow / 2.0, underflow / 2.0),
+      // Otherwise the width takes up the space, and `auto` margins are zero.
+      (None, left, right) => {
+        let (left, right) = (left.unwrap_or(0.0), right.unwrap_or(0.0));
+        if underflow >= 0.0 {
+          (underflow, left, right)
+        } else {
+          (0.0, left, right + underflow)
+        }
+      }
+    };
+
+    let d = &mut self.dimensions;
+    d.content.width = width;
+    d.margin.left = margin_left;
+    d.margin.right = margin_right;
+    d.border.left = border_left;
+    d.border.right = border_right;
+    d.padding.left = padding_left;
+    d.padding.right = padding_right;
+  }
+
+  // Compute the vertical edges of the box, and place it below the content laid out in
+  // the containing block so far.
+  fn calculate_block_position(&mut self, containing_block: Dimensions) {
+    let margin_top = self.length("margin-top", containing_block);
+    let margin_bottom = self.length("margin-bottom", containing_block);
+    let border_top = self.border_width("top");
+    let border_bottom = self.border_width("bottom");
+    let padding_top = self.length("padding-top", containing_block);
+    let padding_bottom = self.length("padding-bottom", containing_block);
+
+    let d = &mut self.dimensions;
+    d.margin.top = margin_top;
+    d.margin.bottom = margin_bottom;
+    d.border.top = border_top;
+    d.border.bottom = border_bottom;
+    d.padding.top = padding_top;
+    d.padding.bottom = padding_bottom;
+
+    d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+    d.content.y = containing_block.content.y
+      + containing_block.content.height
+      + d.margin.top
+      + d.border.top
+      + d.padding.top;
+  }
+
+  // Lay out the children one below the other, growing the content height to fit them.
+  // Vertical margins that touch collapse into one, as large as the largest of them, so
+  // the margin between two children is the larger of the two. The first and last child's
+  // margins collapse with this box's own if no border or padding separates them, and then
+  // stick out of it.
+  // Floats are placed where the next child would go, and a child that clears floats moves
+  // down below them.
+  // See: https://www.w3.org/TR/CSS2/box.html#collapsing-margins
+  fn layout_block_children(&mut self, floats: &mut FloatContext, measurer: &dyn TextMeasurer) {
+    let d = self.dimensions;
+    let collapses = self.collapses_margins_with_children();
+    let collapse_top = collapses && d.border.top == 0.0 && d.padding.top == 0.0;
+    let collapse_bottom = collapses
+      && d.border.bottom == 0.0
+      && d.padding.bottom == 0.0
+      && self.height("height").is_none();
+    // The bottom margin of the child before, leaving out absolutely positioned ones.
+    let mut previous_bottom = None;
+    for i in 0..self.children.len() {
+      let content = self.dimensions.content;
+      let child = &mut self.children[i];
+      if child.is_absolutely_positioned() {
+        // It is laid out once this box is, in `layout_positioned`, and starts out where it
+        // would have been in the flow.
+        child.dimensions.content.x = content.x;
+        child.dimensions.content.y = content.y + content.height;
+        continue;
+      }
+      if child.float() != Float::None {
+        child.layout_float(self.dimensions, content.y + content.height, floats, measurer);
+        child.apply_relative_offset(self.dimensions);
+        continue;
+      }
+      // The floats the child places move along with it.
+      let first_float = floats.floats.len();
+      let containing_block = self.dimensions;
+      child.layout(containing_block, floats, measurer);
+      let laid_out_top = child.dimensions.border_box().y;
+      let margin_top = self.children[i].dimensions.margin.top;
+      match previous_bottom {
+        Some(previous_bottom) => {
+          let collapsed = collapse_margins(previous_bottom, margin_top);
+          let overlap = previous_bottom + margin_top - collapsed;
+          self.children[i].translate(0.0, -overlap);
+          floats.translate(first_float, -overlap);
+          self.dimensions.content.height -= overlap;
+        }
+        None if collapse_top => {
+          // This box moves down if the child's margin is larger than its own, and the child
+          // moves up to its top.
+          let margin = collapse_margins(self.dimensions.margin.top, margin_top);
+          let shift = margin - self.dimensions.margin.top;
+          self.dimensions.margin.top = margin;
+          self.dimensions.content.y += shift;
+          self.children[i].translate(0.0, shift - margin_top);
+          floats.translate(first_float, shift - margin_top);
+          self.dimensions.content.height -= margin_top;
+        }
+        None => {}
+      }
+      // Clearance moves the child's border box below the floats it clears. Its lines no
+      // longer go around those floats, so it is laid out again there.
+      let border_top = self.children[i].dimensions.border_box().y;
+      let clear = self.children[i].clear();
+      if let Some(bottom) = floats.clearance(clear, first_float).filter(|&b| b > border_top) {
+        floats.floats.truncate(first_float);
+        let mut containing_block = containing_block;
+        containing_block.content.height += bottom - laid_out_top;
+        self.children[i].layout(containing_block, floats, measurer);
+        self.dimensions.content.height += bottom - border_top;
+      }
+      self.dimensions.content.height += self.children[i].dimensions.margin_box().height;
+      previous_bottom = Some(self.children[i].dimensions.margin.bottom);
+      self.children[i].apply_relative_offset(self.dimensions);
+    }
+    if let Some(margin_bottom) = previous_bottom.filter(|_| collapse_bottom) {
+      self.dimensions.content.height -= margin_bottom;
+      let margin = collapse_margins(self.dimensions.margin.bottom, margin_bottom);
+      self.dimensions.margin.bottom = margin;
+    }
+  }
+
+  // Move a relatively positioned box by its offsets, leaving the boxes around it where they
+  // are. `left` wins over `right`, and `top` over `bottom`.
+  // See: https://www.w3.org/TR/CSS2/visuren.html#relative-positioning
+  fn apply_relative_offset(&mut self, containing_block: Dimensions) {
+    if self.position() != Position::Relative {
+      return;
+    }
+    let width = Some(containing_block.content.width);
+    let dx = match (self.offset("left", width), self.offset("right", width)) {
+      (Some(left), _) => left,
+      (None, Some(right)) => -right,
+      (None, None) => 0.0,
+    };
+    // The containing block's height isn't known yet, so percentages count as `auto`.
+    let dy = match (self.offset("top", None), self.offset("bottom", None)) {
+      (Some(top), _) => top,
+      (None, Some(bottom)) => -bottom,
+      (None, None) => 0.0,
+    };
+    self.translate(dx, dy);
+  }
+
+  // Lay out the absolutely positioned descendants of a box that has been laid out.
+  // `containing_block` is the padding box of the nearest positioned box above them, and
+  // `viewport` is what fixed boxes are placed in.
+  fn layout_positioned(
+    &mut self,
+    containing_block: Rect,
+    viewport: Rect,
+    measurer: &dyn TextMeasurer,
+  ) {
+    for child in &mut self.children {
+      match child.position() {
+        Position::Absolute => child.layout_absolute(containing_block, measurer),
+        Position::Fixed => child.layout_absolute(viewport, measurer),
+        Position::Static | Position::Relative => {}
+      }
+      let containing_block = match child.position() {
+        Position::Static => containing_block,
+        _ => child.dimensions.padding_box(),
+      };
+      child.layout_positioned(containing_block, viewport, measurer);
+    }
+  }
+
+  // Lay out an absolutely positioned box in `containing_block`. Its offsets say how far its
+  // margin box is from each side, and if they are `auto`, it stays where it would have been
+  // in the flow. Unless `width` is set, it stretches to the right offset, or the right side
+  // of the containing block, instead of shrinking to fit its contents.
+  // See: https://www.w3.org/TR/CSS2/visudet.html#abs-non-replaced-width
+  fn layout_absolute(&mut self, containing_block: Rect, measurer: &dyn TextMeasurer) {
+    let cb = containing_block;
+    let left = self.offset("left", Some(cb.width));
+    let right = self.offset("right", Some(cb.width));
+    let top = self.offset("top", Some(cb.height));
+    let bottom = self.offset("bottom", Some(cb.height));
+    let x = left.map_or(self.dimensions.content.x, |left| cb.x + left);
+    let y = top.map_or(self.dimensions.content.y, |top| cb.y + top);
+    let area = Dimensions {
+      content: Rect {
+        x,
+        y,
+        width: cb.x + cb.width - right.unwrap_or(0.0) - x,
+        height: 0.0,
+      },
+      ..Dimensions::default()
+    };
+    self.layout_block(area, &mut FloatContext::default(), measurer);
+
+    // With both `top` and `bottom` set, an `auto` height fills the space between them.
+    if let (Some(top), Some(bottom), None) = (top, bottom, self.height("height")) {
+      let edges = self.dimensions.margin_box().height - self.dimensions.content.height;
+      self.dimensions.content.height = (cb.height - top - bottom - edges).max(0.0);
+      self.calculate_block_height();
+    }
+    // Otherwise the box may need moving to line up with the right or bottom offset.
+    let margin_box = self.dimensions.margin_box();
+    let dx = match (left, right) {
+      (None, Some(right)) => cb.x + cb.width - right - (margin_box.x + margin_box.width),
+      _ => 0.0,
+    };
+    let dy = match (top, bottom) {
+      (None, Some(bottom)) => cb.y + cb.height - bottom - (margin_box.y + margin_box.height),
+      _ => 0.0,
+    };
+    self.translate(dx, dy);
+  }
+
+  // Lay out a float in `containing_block`, with its top no higher than `y`, and place it
+  // beside the floats before it.
+  fn layout_float(
+    &mut self,
+    containing_block: Dimensions,
+    y: f32,
+    floats: &mut FloatContext,
+    measurer: &dyn TextMeasurer,
+  ) {
+    let mut area = containing_block;
+    area.content.y = y;
+    area.content.height = 0.0;
+    self.layout_block(area, floats, measurer);
+    place_float(self, floats, containing_block.content);
+  }
+
+  // Can the box's margins collapse with its children's? They can for blocks, unless they
+  // start a new block formatting context.
+  fn collapses_margins_with_children(&self) -> bool {
+    matches!(self.box_type, BoxType::BlockNode(_) | BoxType::AnonymousBlock)
+      && !self.establishes_formatting_context()
+  }
+
+  // Does the box start a new block formatting context, which keeps the floats inside it
+  // apart from those outside? The document and its root element, which is taken to be
+  // `<html>`, do, as do inline-blocks, floats and absolutely positioned boxes.
+  // See: https://www.w3.org/TR/CSS2/visuren.html#block-formatting
+  fn establishes_formatting_context(&self) -> bool {
+    match self.box_type {
+      BoxType::BlockNode(_) if self.is_absolutely_positioned() => true,
+      BoxType::BlockNode(_) if self.float() != Float::None => true,
+      BoxType::BlockNode(styled) => match styled.node.node_type {
+        NodeType::Document(_) => true,
+        NodeType::Element(ref element) => element.tag_name == "html",
+        _ => false,
+      },
+      BoxType::InlineBlockNode(_) => true,
+      BoxType::InlineNode(_) | BoxType::AnonymousBlock => false,
+    }
+  }
+
+  // The `float` of the box's node, `none` for boxes that aren't blocks, and for absolutely
+  // positioned ones, which can't float.
+  fn float(&self) -> Float {
+    match self.box_type {
+      BoxType::BlockNode(_) if self.is_absolutely_positioned() => Float::None,
+      BoxType::BlockNode(styled) => styled.float(),
+      _ => Float::None,
+    }
+  }
+
+  // The `clear` of the box's node, `none` for boxes that aren't blocks.
+  fn clear(&self) -> Clear {
+    match self.box_type {
+      BoxType::BlockNode(styled) => styled.clear(),
+      _ => Clear::None,
+    }
+  }
+
+  // The `position` of the box's node, `static` for anonymous and inline boxes.
+  fn position(&self) -> Position {
+    match self.box_type {
+      BoxType::BlockNode(styled) | BoxType::InlineBlockNode(styled) => styled.position(),
+      BoxType::InlineNode(_) | BoxType::AnonymousBlock => Position::Static,
+    }
+  }
+
+  fn is_absolutely_positioned(&self) -> bool {
+    matches!(self.position(), Position::Absolute | Position::Fixed)
+  }
+
+  // Lay out the inline-level children in lines as wide as the box, which is as tall as
+  // its lines. Lines wrap between words, and a word too long for a line of its own sticks
+  // out of it. Items are lined up at the bottom of each line, which stands in for their
+  // baseline. Lines are shortened to make room for floats, and move down below them if
+  // not even one piece fits beside them.
+  // The margins, borders and padding of inline boxes are ignored, and an inline-block with
+  // an `auto` width fills the line instead of shrinking to fit its contents.
+  // See: https://www.w3.org/TR/CSS2/visuren.html#inline-formatting
+  fn layout_inline(&mut self, floats: &mut FloatContext, measurer: &dyn TextMeasurer) {
+    let content = self.dimensions.content;
+    let mut pieces = Vec::new();
+    let mut space = false;
+    for child in &mut self.children {
+      collect_inline_pieces(child, &mut pieces, &mut space);
+    }
+
+    let mut lines = Vec::new();
+    let mut line = LineBuilder::new(content.x, content.y);
+    // Floats that come partway through a line, which are placed below it.
+    let mut next_line_floats: Vec<&mut LayoutBox> = Vec::new();
+    for piece in pieces {
+      match piece {
+        InlinePiece::Word {
+          text,
+          styled,
+          font_size,
+          space_before,
+        } => {
+          let width = measurer.text_width(text, font_size);
+          let height = font_size * LINE_HEIGHT;
+          let space_width = measurer.text_width(" ", font_size);
+          let space_width = if space_before { space_width } else { 0.0 };
+          if line.is_full(width + space_width, height, content, floats) {
+            line.finish(&mut lines);
+            for float in next_line_floats.drain(..) {
+              float.layout_float(self.dimensions, line.y, floats, measurer);
+            }
+          }
+          line.start_beside_floats(width, height, content, floats);
+          line.push_word(text, styled, width, height, space_width);
+        }
+        InlinePiece::Block {
+          layout_box,
+          space_before,
+        } => {
+          // It is laid out at the top left of the line, and moved into place once the line
+          // is full.
+          let mut containing_block = self.dimensions;
+          containing_block.content.height = 0.0;
+          layout_box.layout_block(containing_block, floats, measurer);
+          let size = layout_box.dimensions.margin_box();
+          let space_width = measurer.text_width(" ", layout_box.font_size());
+          let space_width = if space_before { space_width } else { 0.0 };
+          if line.is_full(size.width + space_width, size.height, content, floats) {
+            line.finish(&mut lines);
+            for float in next_line_floats.drain(..) {
+              float.layout_float(self.dimensions, line.y, floats, measurer);
+            }
+          }
+          line.start_beside_floats(size.width, size.height, content, floats);
+          line.push_block(layout_box, size, space_width);
+        }
+        // It is laid out later, in `layout_positioned`, and starts out where it is in the
+        // line.
+        InlinePiece::Positioned(layout_box) => {
+          layout_box.dimensions.content.x = line.x + line.width;
+          layout_box.dimensions.content.y = line.y;
+        }
+        InlinePiece::Float(layout_box) if line.items.is_empty() => {
+          layout_box.layout_float(self.dimensions, line.y, floats, measurer);
+        }
+        InlinePiece::Float(layout_box) => next_line_floats.push(layout_box),
+      }
+    }
+    line.finish(&mut lines);
+    for float in next_line_floats {
+      float.layout_float(self.dimensions, line.y, floats, measurer);
+    }
+    self.dimensions.content.height = line.depth;
+    self.lines = lines;
+  }
+
+  // Move the box and its descendants by `dx` to the right and `dy` down.
+  // Inline boxes are left where they are, since they aren't given a position.
+  fn translate(&mut self, dx: f32, dy: f32) {
+    if !matches!(self.box_type, BoxType::InlineNode(_)) {
+      self.dimensions.content.x += dx;
+      self.dimensions.content.y += dy;
+    }
+    for child in &mut self.children {
+      child.translate(dx, dy);
+    }
+    for item in self.lines.iter_mut().flat_map(|line| &mut line.items) {
+      item.rect.x += dx;
+      item.rect.y += dy;
+    }
+  }
+
+  // The height is the height of the children, unless `height` is set, and is then kept
+  // between `min-height` and `max-height`.
+  // See: https://www.w3.org/TR/CSS2/visudet.html#min-max-heights
+  fn calculate_block_height(&mut self) {
+    if let Some(height) = self.height("height") {
+      self.dimensions.content.height = height;
+    }
+    if let Some(max_height) = self.height("max-height") {
+      self.dimensions.content.height = self.dimensions.content.height.min(max_height);
+    }
+    if let Some(min_height) = self.height("min-height") {
+      self.dimensions.content.height = self.dimensions.content.height.max(min_height);
+    }
+  }
+
+  // A height property in px, or `None` if it isn't a length.
+  // The containing block's height isn't known yet, so a percentage counts as unset.
+  fn height(&self, name: &str) -> Option<f32> {
+    match self.specified_values().and_then(|values| values.get(name)) {
+      Some(Value::Length(_, Unit::Percent)) => None,
+      Some(value @ (Value::Length(..) | Value::Calc(_))) => {
+        Some(value.to_px(0.0, self.font_size()))
+      }
+      _ => None,
+    }
+  }
+
+  // The length of a property in px, or zero if it isn't set or isn't a length.
+  // Percentages are of the containing block's width, even for vertical edges.
+  fn length(&self, name: &str, containing_block: Dimensions) -> f32 {
+    self
+      .specified_values()
+      .and_then(|values| values.get(name))
+      .map_or(0.0, |value| {
+        value.to_px(containing_block.content.width, self.font_size())
+      })
+  }
+
+  // An offset like `top` in px, or `None` if it is `auto`. Percentages are of `reference`,
+  // and count as `auto` without one.
+  fn offset(&self, name: &str, reference: Option<f32>) -> Option<f32> {
+    match self.specified_values().and_then(|values| values.get(name)) {
+      Some(Value::Length(_, Unit::Percent)) if reference.is_none() => None,
+      Some(value @ (Value::Length(..) | Value::Calc(_) | Value::Number(_))) => {
+        Some(value.to_px(reference.unwrap_or(0.0), self.font_size()))
+      }
+      _ => None,
+    }
+  }
+
+  // Like `length`, but `None` if the margin is `auto`.
+  fn margin(&self, name: &str, containing_block: Dimensions) -> Option<f32> {
+    match self.specified_values().and_then(|values| values.get(name)) {
+      Some(Value::Keyword(keyword)) if keyword.eq_ignore_ascii_case("auto") => None,
+      _ => Some(self.length(name, containing_block)),
+    }
+  }
+
+  // The font size in px, which `em` lengths are relative to.
+  // The parent's font size isn't known here, so relative font sizes are taken to be
+  // relative to the default.
+  fn font_size(&self) -> f32 {
+    match self.specified_values().and_then(|values| values.get("font-size")) {
+      Some(value @ (Value::Length(..) | Value::Calc(_))) => {
+        value.to_px(DEFAULT_FONT_SIZE, DEFAULT_FONT_SIZE)
+      }
+      _ => DEFAULT_FONT_SIZE,
+    }
+  }
+
+  // The width of one side of the border, e.g. `border-left-width`, falling back to the
+  // `border-width` set by the `border` shorthand.
+  // A border with no style isn't drawn, so its width is zero whatever it is set to.
+  // See: https://www.w3.org/TR/CSS2/box.html#border-width-properties
+  fn border_width(&self, side: &str) -> f32 {
+    let values = match self.specified_values() {
+      Some(values) => values,
+      None => return 0.0,
+    };
+    let style = values
+      .get(&format!("border-{}-style", side))
+      .or_else(|| values.get("border-style"));
+    match style {
+      Some(Value::BorderStyle(BorderStyle::None | BorderStyle::Hidden)) => return 0.0,
+      Some(Value::BorderStyle(_)) => {}
+      _ => return 0.0,
+    }
+    let width = values
+      .get(&format!("border-{}-width", side))
+      .or_else(|| values.get("border-width"));
+    match width {
+      // The keyword widths are the ones most browsers use.
+      Some(Value::Keyword(keyword)) => match &*keyword.to_ascii_lowercase() {
+        "thin" => 1.0,
+        "medium" => 3.0,
+        "thick" => 5.0,
+        _ => 0.0,
+      },
+      Some(value) => value.to_px(0.0, self.font_size()),
+      None => 0.0,
+    }
+  }
+}
+
+// A word or an inline-block, in the order they are laid out in.
+// `space_before` tells whether there is whitespace between it and the piece before.
+enum InlinePiece<'a, 'b> {
+  Word {
+    text: &'a str,
+    styled: &'a StyledNode<'a>,
+    font_size: f32,
+    space_before: bool,
+  },
+  Block {
+    layout_box: &'b mut LayoutBox<'a>,
+    space_before: bool,
+  },
+  // An absolutely positioned box, which takes up no room in the line.
+  Positioned(&'b mut LayoutBox<'a>),
+  Float(&'b mut LayoutBox<'a>),
+}
+
+// Split the inline content of `layout_box` into pieces.
+// `space` is set if the content so far ends with whitespace.
+fn collect_inline_pieces<'a, 'b>(
+  layout_box: &'b mut LayoutBox<'a>,
+  pieces: &mut Vec<InlinePiece<'a, 'b>>,
+  space: &mut bool,
+) {
+  match layout_box.box_type {
+    BoxType::InlineBlockNode(_) => {
+      pieces.push(InlinePiece::Block {
+        layout_box,
+        space_before: std::mem::take(space),
+      });
+    }
+    BoxType::InlineNode(styled) => match styled.node.node_type {
+      NodeType::Text(ref text) => {
+        let font_size = layout_box.font_size();
+        *space |= text.starts_with(|c: char| c.is_ascii_whitespace());
+        for word in text.split(|c: char| c.is_ascii_whitespace()) {
+          if word.is_empty() {
+            continue;
+          }
+          pieces.push(InlinePiece::Word {
+            text: word,
+            styled,
+            font_size,
+            space_before: std::mem::take(space),
+          });
+          *space = true;
+        }
+        *space = text.ends_with(|c: char| c.is_ascii_whitespace());
+      }
+      _ => {
+        for child in &mut layout_box.children {
+          collect_inline_pieces(child, pieces, space);
+        }
+      }
+    },
+    // Only floats and absolutely positioned blocks are found among inline boxes, see
+    // `build_box`.
+    BoxType::BlockNode(_) if layout_box.float() != Float::None => {
+      pieces.push(InlinePiece::Float(layout_box))
+    }
+    BoxType::BlockNode(_) => pieces.push(InlinePiece::Positioned(layout_box)),
+    BoxType::AnonymousBlock => {}
+  }
+}
+
+// The line that inline layout is filling.
+struct LineBuilder<'a, 'b> {
+  x: f32,
+  y: f32,
+  // How much of the line is taken up so far.
+  width: f32,
+  height: f32,
+  // How far below the top of the first line this line starts.
+  depth: f32,
+  items: Vec<InlineItem<'a>>,
+  // The inline-blocks on the line, along with the index of their item.
+  blocks: Vec<(usize, &'b mut LayoutBox<'a>)>,
+}
+
+impl<'a, 'b> LineBuilder<'a, 'b> {
+  fn new(x: f32, y: f32) -> LineBuilder<'a, 'b> {
+    LineBuilder {
+      x,
+      y,
+      width: 0.0,
+      height: 0.0,
+      depth: 0.0,
+      items: Vec::new(),
+      blocks: Vec::new(),
+    }
+  }
+
+  // Is there no room left in `area`, beside the floats, for something of this size? An
+  // empty line always has room, since wrapping wouldn't help.
+  fn is_full(&self, width: f32, height: f32, area: Rect, floats: &FloatContext) -> bool {
+    let (_, right) = floats.available(self.y, self.height.max(height), area);
+    !self.items.is_empty() && self.x + self.width + width > right
+  }
+
+  // Start an empty line beside the floats at its top, moving it down below them until
+  // something of this size fits.
+  fn start_beside_floats(&mut self, width: f32, height: f32, area: Rect, floats: &FloatContext) {
+    if !self.items.is_empty() {
+      return;
+    }
+    loop {
+      let (left, right) = floats.available(self.y, height, area);
+      self.x = left;
+      match floats.next_bottom(self.y, height) {
+        Some(bottom) if width > right - left => {
+          self.depth += bottom - self.y;
+          self.y = bottom;
+        }
+        _ => return,
+      }
+    }
+  }
+
+  // Add a word, joining it to the previous item if it is from the same text.
+  // The space before it is left out at the start of a line.
+  fn push_word(
+    &mut self,
+    text: &str,
+    styled: &'a StyledNode<'a>,
+    width: f32,
+    height: f32,
+    space_width: f32,
+  ) {
+    let space_width = if self.items.is_empty() { 0.0 } else { space_width };
+    self.height = self.height.max(height);
+    match self.items.last_mut() {
+      Some(item) if std::ptr::eq(item.styled, styled) && item.text.is_some() => {
+        let run = item.text.as_mut().unwrap();
+        run.push(' ');
+        run.push_str(text);
+        item.rect.width += space_width + width;
+      }
+      _ => self.items.push(InlineItem {
+        rect: Rect {
+          x: self.x + self.width + space_width,
+          y: 0.0,
+          width,
+          height,
+        },
+        styled,
+        text: Some(text.to_string()),
+      }),
+    }
+    self.width += space_width + width;
+  }
+
+  // Add an inline-block, whose margin box is `size`.
+  fn push_block(&mut self, layout_box: &'b mut LayoutBox<'a>, size: Rect, space_width: f32) {
+    let space_width = if self.items.is_empty() { 0.0 } else { space_width };
+    let styled = match layout_box.box_type {
+      BoxType::InlineBlockNode(styled) => styled,
+      _ => unreachable!("only inline-blocks are laid out as blocks in a line"),
+    };
+    self.height = self.height.max(size.height);
+    self.blocks.push((self.items.len(), layout_box));
+    self.items.push(InlineItem {
+      rect: Rect {
+        x: self.x + self.width + space_width,
+        y: 0.0,
+        ..size
+      },
+      styled,
+      text: None,
+    });
+    self.width += space_width + size.width;
+  }
+
+  // Line up the items at the bottom of the line, add the line to `lines` unless it is
+  // empty, and start a new line below it.
+  fn finish(&mut self, lines: &mut Vec<LineBox<'a>>) {
+    for item in &mut self.items {
+      item.rect.y = self.y + self.height - item.rect.height;
+    }
+    for (index, layout_box) in self.blocks.drain(..) {
+      let rect = self.items[index].rect;
+      let margin_box = layout_box.dimensions.margin_box();
+      layout_box.translate(rect.x - margin_box.x, rect.y - margin_box.y);
+    }
+    if !self.items.is_empty() {
+      lines.push(LineBox {
+        items: std::mem::take(&mut self.items),
+        height: self.height,
+      });
+    }
+    self.y += self.height;
+    self.depth += self.height;
+    self.width = 0.0;
+    self.height = 0.0;
+  }
+}
+
+// The size of two adjoining margins once they collapse: the largest positive margin plus
+// the most negative one.
+fn collapse_margins(a: f32, b: f32) -> f32 {
+  a.max(b).max(0.0) + a.min(b).min(0.0)
+}
+
+// Place a float that has been laid out in `containing_block`, as far up as it fits beside
+// the floats before it, and as far left or right as it can go. It is no higher than it was
+// laid out, or than the floats before it, and it is below the floats that it clears.
+// See: https://www.w3.org/TR/CSS2/visuren.html#float-rules
+fn place_float(layout_box: &mut LayoutBox, floats: &mut FloatContext, containing_block: Rect) {
+  let margin_box = layout_box.dimensions.margin_box();
+  let mut y = floats
+    .floats
+    .iter()
+    .map(|(_, rect)| rect.y)
+    .fold(margin_box.y, f32::max);
+  if let Some(bottom) = floats.clearance(layout_box.clear(), floats.floats.len()) {
+    y = y.max(bottom);
+  }
+  let (left, right) = loop {
+    let (left, right) = floats.available(y, margin_box.height, containing_block);
+    match floats.next_bottom(y, margin_box.height) {
+      Some(bottom) if margin_box.width > right - left => y = bottom,
+      _ => break (left, right),
+    }
+  };
+  let x = match layout_box.float() {
+    Float::Right => right - margin_box.width,
+    _ => left,
+  };
+  layout_box.translate(x - margin_box.x, y - margin_box.y);
+  floats.floats.push((layout_box.float(), Rect { x, y, ..margin_box }));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{css_parser, html_parser, style};
+
+  // Lay out `html` styled with `css` in an 800px wide viewport, and pass the layout tree
+  // to `test`.
+  fn with_layout(html: &str, css: &str, test: impl FnOnce(&LayoutBox)) {
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport = Dimensions::default();
+    viewport.content.width = 800.0;
+    test(&layout(&styled, viewport));
+  }
+
+  // The box of the element with the given `id`.
+  fn find<'b, 'a>(layout_box: &'b LayoutBox<'a>, id: &str) -> &'b LayoutBox<'a> {
+    fn search<'b, 'a>(layout_box: &'b LayoutBox<'a>, id: &str) -> Option<&'b LayoutBox<'a>> {
+      let is_match = match layout_box.box_type {
+        BoxType::BlockNode(styled) | BoxType::InlineBlockNode(styled) => {
+          match styled.node.node_type {
+            NodeType::Element(ref elem) => elem.id() == Some(id),
+            _ => false,
+          }
+        }
+        _ => false,
+      };
+      if is_match {
+        return Some(layout_box);
+      }
+      layout_box.children.iter().find_map(|child| search(child, id))
+    }
+    search(layout_box, id).unwrap_or_else(|| panic!("no box with id {}", id))
+  }
+
+  const BLOCKS: &str = "html, body, div, p { display: block } ";
+
+  #[test]
+  fn blocks_stack_below_each_other() {
+    let css = "#c { padding: 10px } \
+               #a { height: 20px; margin: 5px; padding: 2px; border: 1px solid black } \
+               #b { height: 30px; padding: 3px }";
+    let html = "<div id=c><div id=a></div><div id=b></div></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let (a, b) = (find(root, "a").dimensions, find(root, "b").dimensions);
+      assert_eq!((a.content.x, a.content.y), (18.0, 18.0));
+      assert_eq!((a.content.width, a.content.height), (764.0, 20.0));
+      assert_eq!((b.content.x, b.content.y), (13.0, 49.0));
+      assert_eq!((b.content.width, b.content.height), (774.0, 30.0));
+      assert_eq!(find(root, "c").dimensions.content.height, 72.0);
+    });
+  }
+
+  #[test]
+  fn auto_margins_center_and_auto_width_fills_the_parent() {
+    let css = "#c { width: 600px } \
+               #a { width: 200px; height: 1px; margin: 0 auto; border: 5px solid black } \
+               #b { margin-left: 20px; padding: 10px }";
+    let html = "<div id=c><div id=a></div><div id=b></div></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let a = find(root, "a").dimensions;
+      assert_eq!((a.margin.left, a.margin.right), (195.0, 195.0));
+      assert_eq!((a.content.x, a.content.width), (200.0, 200.0));
+      let b = find(root, "b").dimensions;
+      assert_eq!((b.margin.left, b.margin.right), (20.0, 0.0));
+      assert_eq!((b.content.x, b.content.width), (30.0, 560.0));
+    });
+  }
+
+  #[test]
+  fn content_height_is_the_sum_of_the_children_unless_set() {
+    let css = "#a { height: 10px } #b { height: 25px } #c { height: 40px } \
+               #e { height: 200px }";
+    let html = "<div id=d><div id=a></div><div id=b></div><div id=c></div></div>\
+                <div id=e><div></div></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let ys: Vec<f32> = ["a", "b", "c"]
+        .iter()
+        .map(|id| find(root, id).dimensions.content.y)
+        .collect();
+      assert_eq!(ys, [0.0, 10.0, 35.0]);
+      assert_eq!(find(root, "d").dimensions.content.height, 75.0);
+      let e = find(root, "e").dimensions;
+      assert_eq!((e.content.y, e.content.height), (75.0, 200.0));
+    });
+  }
+
+  #[test]
+  fn cleared_block_is_laid_out_again_below_floats() {
+    // Beside the float, the paragraph has room for one word per line, and below it, for
+    // all of them on one line.
+    let css = "#c { width: 200px } #f { float: left; width: 150px; height: 10px } \
+               p { clear: left }";
+    let html = "<div id=c><div id=f></div><p id=p>aaaa bbbb cccc dddd</p></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let p = find(root, "p").dimensions;
+      assert_eq!(p.content.y, 10.0);
+      assert_eq!(p.content.height, 19.2);
+      assert_eq!(find(root, "c").dimensions.content.height, 10.0 + 19.2);
+    });
+  }
+
+  #[test]
+  fn sibling_margins_collapse_to_the_larger() {
+    let css = "#a { height: 10px; margin-bottom: 20px } #b { height: 10px; margin-top: 30px }";
+    let html = "<div id=c><div id=a></div><div id=b></div></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let (a, b) = (find(root, "a").dimensions, find(root, "b").dimensions);
+      assert_eq!(b.content.y - (a.content.y + 10.0), 30.0);
+      assert_eq!(find(root, "c").dimensions.content.height, 50.0);
+    });
+  }
+
+  #[test]
+  fn parent_margins_collapse_with_first_and_last_child() {
+    let css = "#w { padding-top: 1px } #c { margin-top: 10px; margin-bottom: 5px } \
+               #a { height: 10px; margin-top: 25px; margin-bottom: 15px }";
+    let html = "<div id=w><div id=c><div id=a></div></div></div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let (w, c) = (find(root, "w").dimensions, find(root, "c").dimensions);
+      let a = find(root, "a").dimensions;
+      assert_eq!((c.margin.top, c.margin.bottom), (25.0, 15.0));
+      assert_eq!(c.content.y, w.content.y + 25.0);
+      assert_eq!(a.content.y, c.content.y);
+      assert_eq!(c.content.height, 10.0);
+      // The bottom margin collapses on through `w`, which has no padding at the bottom.
+      assert_eq!(w.content.height, 25.0 + 10.0);
+      assert_eq!(w.margin.bottom, 15.0);
+    });
+
+    // Padding keeps the margins apart.
+    let css = "#c { padding: 1px } #a { height: 10px; margin-top: 25px }";
+    with_layout("<div id=c><div id=a></div></div>", &(BLOCKS.to_string() + css), |root| {
+      let (c, a) = (find(root, "c").dimensions, find(root, "a").dimensions);
+      assert_eq!(c.margin.top, 0.0);
+      assert_eq!(a.content.y, c.content.y + 25.0);
+    });
+  }
+
+  #[test]
+  fn negative_margins_collapse() {
+    // The largest positive margin plus the most negative one.
+    for &(bottom, top, gap) in &[(20.0, -5.0, 15.0), (-10.0, -5.0, -10.0), (-20.0, 5.0, -15.0)] {
+      let css = format!(
+        "#a {{ height: 10px; margin-bottom: {}px }} #b {{ height: 10px; margin-top: {}px }}",
+        bottom, top
+      );
+      let html = "<div id=c><div id=a></div><div id=b></div></div>";
+      with_layout(html, &(BLOCKS.to_string() + &css), |root| {
+        let (a, b) = (find(root, "a").dimensions, find(root, "b").dimensions);
+        assert_eq!(b.content.y - (a.content.y + 10.0), gap);
+        assert_eq!(find(root, "c").dimensions.content.height, 20.0 + gap);
+      });
+    }
+  }
+
+  #[test]
+  fn whitespace_between_blocks_makes_no_boxes() {
+    let css = "#a { height: 10px; margin-bottom: 20px } #b { height: 10px; margin-top: 30px }";
+    let html = "<div id=c>\n  <div id=a></div>\n  <div id=b></div>\n</div>";
+    with_layout(html, &(BLOCKS.to_string() + css), |root| {
+      let c = find(root, "c");
+      assert_eq!(c.children.len(), 2);
+      assert_eq!(c.dimensions.content.height, 50.0);
+    });
+  }
+
+  #[test]
+  fn build_layout_tree_wraps_inline_runs_and_drops_hidden_nodes() {
+    let html = "<body><div id=\"a\"><p></p>a<b>b</b><span>c</span><p></p></div>\
+                <div id=\"b\"></div></body>";
+    let css = "body, div, p { display: block } span { display: none }";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let div = root.get_element_by_id("a").unwrap();
+    let styled = style::style_tree(div, &stylesheet);
+    let layout_box = build_layout_tree(&styled);
+    let kinds: Vec<&str> = layout_box
+      .children
+      .iter()
+      .map(|child| match child.box_type {
+        BoxType::BlockNode(_) => "block",
+        BoxType::AnonymousBlock => "anonymous",
+        _ => "other",
+      })
+      .collect();
+    assert_eq!(kinds, ["block", "anonymous", "block"]);
+    // The text and the `b` element, without the hidden `span`.
+    assert_eq!(layout_box.children[1].children.len(), 2);
+  }
+}
@@ -0,0 +1,11 @@
+// Locating byte offsets in a source, for reporting parse errors.
+
+// The 1-based line and column of the byte offset `position` in `source`.
+// Columns count characters, so a multi-byte character takes up one column.
+pub fn line_column(source: &str, position: usize) -> (usize, usize) {
+  let before = source.get(..position).unwrap_or(source);
+  let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+  let line = before.matches('\n').count() + 1;
+  let column = before[line_start..].chars().count() + 1;
+  (line, column)
+}
@@ -1,13 +1,201 @@
 pub mod css_parser;
+pub mod css_tokenizer;
 pub mod dom;
 pub mod html_parser;
+pub mod html_tokenizer;
+pub mod layout;
+pub mod location;
+pub mod painting;
+pub mod selector;
+pub mod style;
 
+use std::env;
 use std::fs;
+use std::process;
+
+const USAGE: &str =
+    "Usage: learning-browser-engine <html-file> [css-file] [--dump=dom|css|style|layout] [--output=<ppm-file>]";
+
+// What to print after parsing the input files.
+enum Dump {
+    Dom,
+    Css,
+    Style,
+    Layout,
+}
+
+// The size of the window that the document is laid out in.
+const VIEWPORT_WIDTH: f32 = 800.0;
+const VIEWPORT_HEIGHT: f32 = 600.0;
 
 fn main() {
-    let filename = "example.css";
-    let css = fs::read_to_string(filename).expect("Something went wrong");
-    let res = css_parser::parse(css);
+    let mut paths = Vec::new();
+    let mut dump = None;
+    let mut output = None;
+    for arg in env::args().skip(1) {
+        if let Some(mode) = arg.strip_prefix("--dump=") {
+            dump = match mode {
+                "dom" => Some(Dump::Dom),
+                "css" => Some(Dump::Css),
+                "style" => Some(Dump::Style),
+                "layout" => Some(Dump::Layout),
+                _ => exit_with_usage(&format!("Unknown dump mode '{}'", mode)),
+            };
+        } else if let Some(path) = arg.strip_prefix("--output=") {
+            output = Some(path.to_string());
+        } else if arg.starts_with("--") {
+            exit_with_usage(&format!("Unknown option '{}'", arg));
+        } else {
+            paths.push(arg);
+        }
+    }
+    let (html_path, css_path) = match paths.as_slice() {
+        [html] => (html, None),
+        [html, css] => (html, Some(css)),
+        _ => exit_with_usage("Expected an HTML file and an optional CSS file"),
+    };
+    // Without a flag, show the styled tree if there is a stylesheet, or the DOM otherwise.
+    // Nothing is shown by default when painting to a file.
+    let dump = match (dump, &output) {
+        (None, None) => Some(match css_path {
+            Some(_) => Dump::Style,
+            None => Dump::Dom,
+        }),
+        (dump, _) => dump,
+    };
+
+    let root = match html_parser::parse(read_source(html_path)) {
+        Ok(root) => root,
+        Err(error) => exit_with_error(&format!("Failed to parse {}: {}", html_path, error)),
+    };
+    let stylesheet = css_path.map(|path| match css_parser::parse(read_source(path)) {
+        Ok(stylesheet) => stylesheet,
+        Err(error) => exit_with_error(&format!("Failed to parse {}: {}", path, error)),
+    });
+
+    let mut viewport = layout::Dimensions::default();
+    viewport.content.width = VIEWPORT_WIDTH;
+    match (dump, &stylesheet) {
+        (None, _) => {}
+        (Some(Dump::Dom), _) => println!("{:#?}", root),
+        (Some(Dump::Css), Some(stylesheet)) => println!("{:#?}", stylesheet),
+        (Some(Dump::Style), Some(stylesheet)) => {
+            print_style_tree(&style::style_tree(&root, stylesheet), 0)
+        }
+        (Some(Dump::Layout), Some(stylesheet)) => {
+            let styled = style::style_tree(&root, stylesheet);
+            print_layout_tree(&layout::layout(&styled, viewport), 0);
+        }
+        (Some(_), None) => {
+            exit_with_usage("A CSS file is required for --dump=css, style and layout")
+        }
+    }
+
+    if let Some(path) = output {
+        let stylesheet = match stylesheet {
+            Some(stylesheet) => stylesheet,
+            None => exit_with_usage("A CSS file is required for --output"),
+        };
+        let styled = style::style_tree(&root, &stylesheet);
+        let layout_root = layout::layout(&styled, viewport);
+        let mut bounds = viewport.content;
+        bounds.height = VIEWPORT_HEIGHT;
+        let canvas = painting::paint(&layout_root, bounds);
+        if let Err(error) = canvas.save_ppm(&path) {
+            exit_with_error(&format!("Failed to write {}: {}", path, error));
+        }
+    }
+}
+
+// Print each element with its specified values, indented by depth.
+fn print_style_tree(styled_node: &style::StyledNode, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match styled_node.node.node_type {
+        dom::NodeType::Element(ref elem) => {
+            println!("{}<{}>", indent, elem.tag_name);
+            let mut values: Vec<_> = styled_node.specified_values.iter().collect();
+            values.sort_by_key(|&(name, _)| name);
+            for (name, value) in values {
+                println!("{}  {}: {:?}", indent, name, value);
+            }
+        }
+        dom::NodeType::Text(ref text) => println!("{}{:?}", indent, text),
+        // The document itself isn't printed, so its children aren't indented.
+        dom::NodeType::Document(_) => {
+            for child in &styled_node.children {
+                print_style_tree(child, depth);
+            }
+            return;
+        }
+        _ => {}
+    }
+    for child in &styled_node.children {
+        print_style_tree(child, depth + 1);
+    }
+}
+
+// Print each box with the position and size of its content area, indented by depth.
+fn print_layout_tree(layout_box: &layout::LayoutBox, depth: usize) {
+    let name = match layout_box.box_type {
+        layout::BoxType::BlockNode(styled)
+        | layout::BoxType::InlineNode(styled)
+        | layout::BoxType::InlineBlockNode(styled) => {
+            match styled.node.node_type {
+                dom::NodeType::Element(ref elem) => elem.tag_name.as_str(),
+                dom::NodeType::Text(_) => "#text",
+                _ => "#document",
+            }
+        }
+        layout::BoxType::AnonymousBlock => "#anonymous",
+    };
+    let content = layout_box.dimensions.content;
+    println!(
+        "{}{} x={} y={} width={} height={}",
+        "  ".repeat(depth),
+        name,
+        content.x,
+        content.y,
+        content.width,
+        content.height
+    );
+    for line in &layout_box.lines {
+        println!("{}  line height={}", "  ".repeat(depth), line.height);
+        for item in &line.items {
+            let rect = item.rect;
+            let text = match item.text {
+                Some(ref text) => format!("{:?}", text),
+                None => "#inline-block".to_string(),
+            };
+            println!(
+                "{}    {} x={} y={} width={} height={}",
+                "  ".repeat(depth),
+                text,
+                rect.x,
+                rect.y,
+                rect.width,
+                rect.height
+            );
+        }
+    }
+    for child in &layout_box.children {
+        print_layout_tree(child, depth + 1);
+    }
+}
+
+// Read a whole file, exiting with an error message if it can't be read.
+fn read_source(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => exit_with_error(&format!("Failed to read {}: {}", path, error)),
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2);
+}
 
-    println!("{:?}", res);
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
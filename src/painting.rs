@@ -0,0 +1,227 @@
+use super::css_parser::{Color, Value};
+use super::layout::{LayoutBox, Rect};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// A grid of pixels, stored row by row from the top left.
+#[derive(Debug)]
+pub struct Canvas {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<Color>,
+}
+
+// Something to draw, in document coordinates.
+#[derive(Debug)]
+pub enum DisplayCommand {
+  SolidColor(Color, Rect),
+}
+
+// Paint a layout tree onto a canvas that shows the part of the document inside `bounds`.
+// The canvas starts out white, and boxes are painted in tree order, so children end up on
+// top of their parents.
+pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+  let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+  for command in build_display_list(layout_root) {
+    match command {
+      DisplayCommand::SolidColor(color, rect) => {
+        let rect = Rect {
+          x: rect.x - bounds.x,
+          y: rect.y - bounds.y,
+          ..rect
+        };
+        canvas.fill_rect(&color, rect);
+      }
+    }
+  }
+  canvas
+}
+
+// List what to draw for a layout tree, from back to front.
+pub fn build_display_list(layout_root: &LayoutBox) -> Vec<DisplayCommand> {
+  let mut list = Vec::new();
+  render_layout_box(&mut list, layout_root);
+  list
+}
+
+fn render_layout_box(list: &mut Vec<DisplayCommand>, layout_box: &LayoutBox) {
+  render_background(list, layout_box);
+  render_borders(list, layout_box);
+  for child in &layout_box.children {
+    render_layout_box(list, child);
+  }
+}
+
+// The background fills the border box, so it shows through gaps in the border.
+// See: https://www.w3.org/TR/CSS2/colors.html#background
+fn render_background(list: &mut Vec<DisplayCommand>, layout_box: &LayoutBox) {
+  // `background: <color>` isn't expanded into its longhands, so it is looked at as well.
+  if let Some(color) = get_color(layout_box, "background-color")
+    .or_else(|| get_color(layout_box, "background"))
+  {
+    list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box()));
+  }
+}
+
+fn render_borders(list: &mut Vec<DisplayCommand>, layout_box: &LayoutBox) {
+  let d = &layout_box.dimensions;
+  let border_box = d.border_box();
+  let sides = [
+    (
+      "top",
+      Rect {
+        height: d.border.top,
+        ..border_box
+      },
+    ),
+    (
+      "right",
+      Rect {
+        x: border_box.x + border_box.width - d.border.right,
+        width: d.border.right,
+        ..border_box
+      },
+    ),
+    (
+      "bottom",
+      Rect {
+        y: border_box.y + border_box.height - d.border.bottom,
+        height: d.border.bottom,
+        ..border_box
+      },
+    ),
+    (
+      "left",
+      Rect {
+        width: d.border.left,
+        ..border_box
+      },
+    ),
+  ];
+  for (side, rect) in sides {
+    let color = get_color(layout_box, &format!("border-{}-color", side))
+      .or_else(|| get_color(layout_box, "border-color"));
+    if let Some(color) = color {
+      list.push(DisplayCommand::SolidColor(color, rect));
+    }
+  }
+}
+
+// The color a property is set to, or `None` if it isn't set to a color.
+fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+  match layout_box.specified_values()?.get(name) {
+    Some(Value::ColorValue(color)) => Some(color.clone()),
+    _ => None,
+  }
+}
+
+impl Canvas {
+  // A white canvas.
+  pub fn new(width: usize, height: usize) -> Canvas {
+    let white = Color {
+      r: 255,
+      g: 255,
+      b: 255,
+      a: 255,
+    };
+    Canvas {
+      width,
+      height,
+      pixels: vec![white; width * height],
+    }
+  }
+
+  // Fill a rectangle, given in canvas coordinates, blending the color over what is there.
+  // The rectangle is clipped to the canvas, and a pixel is filled if its top left corner
+  // is inside it.
+  pub fn fill_rect(&mut self, color: &Color, rect: Rect) {
+    let clamp = |value: f32, max: usize| value.ceil().clamp(0.0, max as f32) as usize;
+    let (x0, x1) = (clamp(rect.x, self.width), clamp(rect.x + rect.width, self.width));
+    let (y0, y1) = (clamp(rect.y, self.height), clamp(rect.y + rect.height, self.height));
+    for y in y0..y1 {
+      for x in x0..x1 {
+        let pixel = &mut self.pixels[y * self.width + x];
+        *pixel = blend(color, pixel);
+      }
+    }
+  }
+
+  // Write the canvas to a binary PPM (P6) image, which most image viewers can open.
+  // PPM has no alpha channel, so it is dropped.
+  // See: https://netpbm.sourceforge.net/doc/ppm.html
+  pub fn save_ppm(&self, path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+    for pixel in &self.pixels {
+      file.write_all(&[pixel.r, pixel.g, pixel.b])?;
+    }
+    file.flush()
+  }
+}
+
+// Draw `source` over `backdrop`, see: https://www.w3.org/TR/compositing-1/#simplealphacompositing
+fn blend(source: &Color, backdrop: &Color) -> Color {
+  let alpha = source.a as f32 / 255.0;
+  let backdrop_alpha = backdrop.a as f32 / 255.0 * (1.0 - alpha);
+  let result_alpha = alpha + backdrop_alpha;
+  if result_alpha == 0.0 {
+    return source.clone();
+  }
+  let channel = |s: u8, b: u8| {
+    ((s as f32 * alpha + b as f32 * backdrop_alpha) / result_alpha).round() as u8
+  };
+  Color {
+    r: channel(source.r, backdrop.r),
+    g: channel(source.g, backdrop.g),
+    b: channel(source.b, backdrop.b),
+    a: (result_alpha * 255.0).round() as u8,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::layout::{self, Dimensions};
+  use crate::{css_parser, html_parser, style};
+
+  fn color(r: u8, g: u8, b: u8) -> Color {
+    Color { r, g, b, a: 255 }
+  }
+
+  #[test]
+  fn paints_boxes_where_they_are_laid_out() {
+    let html = "<div id=a></div><div id=b></div>";
+    let css = "div { display: block; width: 10px; height: 10px } \
+               #a { background-color: #f00 } #b { background-color: #00f; margin-left: 10px }";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let styled = style::style_tree(&root, &stylesheet);
+    let mut viewport = Dimensions::default();
+    viewport.content.width = 30.0;
+    let layout_root = layout::layout(&styled, viewport);
+    let bounds = Rect { x: 0.0, y: 0.0, width: 30.0, height: 30.0 };
+    let canvas = paint(&layout_root, bounds);
+    let pixel = |x: usize, y: usize| &canvas.pixels[y * canvas.width + x];
+    assert_eq!(pixel(0, 0), &color(255, 0, 0));
+    assert_eq!(pixel(9, 9), &color(255, 0, 0));
+    assert_eq!(pixel(10, 10), &color(0, 0, 255));
+    assert_eq!(pixel(19, 19), &color(0, 0, 255));
+    assert_eq!(pixel(10, 0), &color(255, 255, 255));
+    assert_eq!(pixel(0, 10), &color(255, 255, 255));
+    assert_eq!(pixel(20, 20), &color(255, 255, 255));
+  }
+
+  #[test]
+  fn save_ppm_writes_a_header_and_the_pixels() {
+    let mut canvas = Canvas::new(2, 1);
+    canvas.pixels[1] = Color { r: 1, g: 2, b: 3, a: 128 };
+    let path = std::env::temp_dir().join(format!("save_ppm_{}.ppm", std::process::id()));
+    canvas.save_ppm(path.to_str().unwrap()).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut expected = b"P6\n2 1\n255\n".to_vec();
+    expected.extend_from_slice(&[255, 255, 255, 1, 2, 3]);
+    assert_eq!(bytes, expected);
+  }
+}
+
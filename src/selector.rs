@@ -0,0 +1,456 @@
+use super::css_parser::{
+  self, AttrOp, AttributeSelector, Combinator, PseudoClass, Selector, SimpleSelector,
+};
+use super::dom::{ElementData, Node, NodeType};
+use std::rc::Rc;
+
+// An element above the one being styled or matched, along with the element siblings
+// that come before it (nearest last). These are needed to match combinators.
+pub struct Ancestor<'a> {
+  pub elem: &'a ElementData,
+  pub preceding_siblings: Vec<&'a ElementData>,
+}
+
+// The element siblings and ancestors of a single node, found by following its parent and
+// sibling links. It keeps the nodes alive so that the `Ancestor`s can borrow from them.
+pub struct Context {
+  preceding_siblings: Vec<Rc<Node>>,
+  ancestors: Vec<(Rc<Node>, Vec<Rc<Node>>)>,
+}
+
+impl Context {
+  // A node that isn't linked into a tree gets an empty context, as if it were the root.
+  pub fn of(node: &Node) -> Context {
+    let mut ancestors = Vec::new();
+    let mut parent = node.parent();
+    while let Some(node) = parent {
+      // The document and anything else above the root element can't be matched.
+      if !matches!(node.node_type, NodeType::Element(_)) {
+        break;
+      }
+      parent = node.parent();
+      let siblings = element_siblings_before(&node);
+      ancestors.push((node, siblings));
+    }
+    ancestors.reverse();
+    Context {
+      preceding_siblings: element_siblings_before(node),
+      ancestors,
+    }
+  }
+
+  // The element siblings before the node, nearest last.
+  pub fn preceding_siblings(&self) -> Vec<&ElementData> {
+    elements(&self.preceding_siblings)
+  }
+
+  // The elements above the node, nearest last.
+  pub fn ancestors(&self) -> Vec<Ancestor<'_>> {
+    self
+      .ancestors
+      .iter()
+      .filter_map(|(node, siblings)| match node.node_type {
+        NodeType::Element(ref elem) => Some(Ancestor {
+          elem,
+          preceding_siblings: elements(siblings),
+        }),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+fn element_siblings_before(node: &Node) -> Vec<Rc<Node>> {
+  let mut siblings = Vec::new();
+  let mut sibling = node.prev_sibling();
+  while let Some(node) = sibling {
+    sibling = node.prev_sibling();
+    if let NodeType::Element(_) = node.node_type {
+      siblings.push(node);
+    }
+  }
+  siblings.reverse();
+  siblings
+}
+
+fn elements(nodes: &[Rc<Node>]) -> Vec<&ElementData> {
+  nodes
+    .iter()
+    .filter_map(|node| match node.node_type {
+      NodeType::Element(ref elem) => Some(elem),
+      _ => None,
+    })
+    .collect()
+}
+
+impl Node {
+  // Find the first element matching `selector`, searching this node and its
+  // descendants depth-first in document order.
+  pub fn find_first(&self, selector: &Selector) -> Option<&Node> {
+    let mut found = Vec::new();
+    find_matches(self, std::slice::from_ref(selector), &[], &mut Vec::new(), &mut found, true);
+    found.pop()
+  }
+
+  // Find every element matching `selector` in this node and its descendants,
+  // in document order.
+  pub fn find_all(&self, selector: &Selector) -> Vec<&Node> {
+    let mut found = Vec::new();
+    find_matches(self, std::slice::from_ref(selector), &[], &mut Vec::new(), &mut found, false);
+    found
+  }
+
+  // Find the first element matching a selector list like `ul > li, p.note`, in document
+  // order. An invalid selector list matches nothing.
+  pub fn query_selector(&self, selectors: &str) -> Option<&Node> {
+    let selectors = css_parser::parse_selector_list(selectors.to_string()).ok()?;
+    let mut found = Vec::new();
+    find_matches(self, &selectors, &[], &mut Vec::new(), &mut found, true);
+    found.pop()
+  }
+
+  // Find every element matching a selector list like `ul > li, p.note`, in document
+  // order and without duplicates. An invalid selector list matches nothing.
+  pub fn query_selector_all(&self, selectors: &str) -> Vec<&Node> {
+    let selectors = match css_parser::parse_selector_list(selectors.to_string()) {
+      Ok(selectors) => selectors,
+      Err(_) => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    find_matches(self, &selectors, &[], &mut Vec::new(), &mut found, false);
+    found
+  }
+}
+
+// Walk the subtree like `style::style_tree`, keeping track of the siblings and ancestors
+// needed to match combinators. Each element is visited once and kept if any of
+// `selectors` matches it, so the matches come in document order without duplicates.
+// The elements above the starting node are not known, so it is matched as if it were
+// the root. Returns true once `first_only` is satisfied.
+fn find_matches<'a>(
+  node: &'a Node,
+  selectors: &[Selector],
+  preceding_siblings: &[&'a ElementData],
+  ancestors: &mut Vec<Ancestor<'a>>,
+  found: &mut Vec<&'a Node>,
+  first_only: bool,
+) -> bool {
+  let elem = match node.node_type {
+    NodeType::Element(ref elem) => elem,
+    // The elements in a document are matched as roots.
+    NodeType::Document(_) => {
+      let mut preceding_siblings = Vec::new();
+      for child in &node.children {
+        if find_matches(child, selectors, &preceding_siblings, ancestors, found, first_only) {
+          return true;
+        }
+        if let NodeType::Element(ref child_elem) = child.node_type {
+          preceding_siblings.push(child_elem);
+        }
+      }
+      return false;
+    }
+    _ => return false,
+  };
+  if selectors
+    .iter()
+    .any(|selector| matches_in_context(selector, elem, preceding_siblings, ancestors))
+  {
+    found.push(node);
+    if first_only {
+      return true;
+    }
+  }
+  ancestors.push(Ancestor {
+    elem,
+    preceding_siblings: preceding_siblings.to_vec(),
+  });
+  let mut child_siblings = Vec::new();
+  let mut done = false;
+  for child in &node.children {
+    if find_matches(child, selectors, &child_siblings, ancestors, found, first_only) {
+      done = true;
+      break;
+    }
+    if let NodeType::Element(ref child_elem) = child.node_type {
+      child_siblings.push(child_elem);
+    }
+  }
+  ancestors.pop();
+  done
+}
+
+// Test whether `selector` matches `node`, looking at its ancestors and siblings through
+// its links for combinators. Only elements can match.
+pub fn matches(selector: &Selector, node: &Node) -> bool {
+  match node.node_type {
+    NodeType::Element(ref elem) => {
+      let context = Context::of(node);
+      matches_in_context(selector, elem, &context.preceding_siblings(), &context.ancestors())
+    }
+    _ => false,
+  }
+}
+
+// Test whether `selector` matches `elem`, given the element siblings before it and the
+// elements above it, both nearest last.
+pub fn matches_in_context(
+  selector: &Selector,
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  ancestors: &[Ancestor],
+) -> bool {
+  match *selector {
+    Selector::Simple(ref simple) => matches_simple_selector(elem, preceding_siblings, simple),
+    Selector::Complex(ref complex) => {
+      let mut parts: Vec<(Option<&Combinator>, &SimpleSelector)> = vec![(None, &complex.first)];
+      parts.extend(
+        complex
+          .rest
+          .iter()
+          .map(|(combinator, simple)| (Some(combinator), simple)),
+      );
+      matches_complex(elem, preceding_siblings, ancestors, &parts)
+    }
+  }
+}
+
+// Match a complex selector from right to left: the last part must match `elem`,
+// and the rest must match its ancestors or siblings as required by each combinator.
+fn matches_complex(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  ancestors: &[Ancestor],
+  parts: &[(Option<&Combinator>, &SimpleSelector)],
+) -> bool {
+  let (&(combinator, simple), rest) = match parts.split_last() {
+    Some(last) => last,
+    None => return true,
+  };
+  if !matches_simple_selector(elem, preceding_siblings, simple) {
+    return false;
+  }
+  match combinator {
+    None => true,
+    Some(Combinator::Child) => match ancestors.split_last() {
+      Some((parent, ancestors)) => {
+        matches_complex(parent.elem, &parent.preceding_siblings, ancestors, rest)
+      }
+      None => false,
+    },
+    Some(Combinator::Descendant) => (0..ancestors.len()).rev().any(|i| {
+      let ancestor = &ancestors[i];
+      matches_complex(ancestor.elem, &ancestor.preceding_siblings, &ancestors[..i], rest)
+    }),
+    Some(Combinator::AdjacentSibling) => match preceding_siblings.split_last() {
+      Some((sibling, preceding_siblings)) => {
+        matches_complex(sibling, preceding_siblings, ancestors, rest)
+      }
+      None => false,
+    },
+    Some(Combinator::GeneralSibling) => (0..preceding_siblings.len())
+      .rev()
+      .any(|i| matches_complex(preceding_siblings[i], &preceding_siblings[..i], ancestors, rest)),
+  }
+}
+
+fn matches_simple_selector(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  selector: &SimpleSelector,
+) -> bool {
+  // A pseudo-element selects generated content rather than the element itself.
+  if selector.pseudo_element.is_some() {
+    return false;
+  }
+
+  // Check type selector, ignoring case like HTML does
+  if selector
+    .tag_name
+    .iter()
+    .any(|name| !elem.tag_name.eq_ignore_ascii_case(name))
+  {
+    return false;
+  }
+
+  // Check ID selector
+  if selector.id.iter().any(|id| elem.id() != Some(id)) {
+    return false;
+  }
+
+  // Check class selectors
+  let elem_classes = elem.classes();
+  if selector
+    .class
+    .iter()
+    .any(|class| !elem_classes.contains(&**class))
+  {
+    return false;
+  }
+
+  // Check attribute selectors
+  if selector
+    .attributes
+    .iter()
+    .any(|attribute| !matches_attribute_selector(elem, attribute))
+  {
+    return false;
+  }
+
+  // Check pseudo-classes
+  if selector
+    .pseudo_classes
+    .iter()
+    .any(|pseudo_class| !matches_pseudo_class(elem, preceding_siblings, pseudo_class))
+  {
+    return false;
+  }
+
+  // We didn't find any non-matching selector components.
+  true
+}
+
+// Attribute names are lowercased by the HTML parser, so they match case-insensitively.
+fn matches_attribute_selector(elem: &ElementData, selector: &AttributeSelector) -> bool {
+  let actual = match elem.attributes.get(&selector.name.to_ascii_lowercase()) {
+    Some(actual) => actual,
+    None => return false,
+  };
+  let expected = match selector.value {
+    Some(ref expected) => expected.as_str(),
+    None => return true,
+  };
+  match selector.op {
+    None => true,
+    Some(AttrOp::Exact) => actual == expected,
+    Some(AttrOp::Includes) => actual.split_whitespace().any(|word| word == expected),
+    Some(AttrOp::DashMatch) => {
+      actual == expected || actual.starts_with(&format!("{}-", expected))
+    }
+    // An empty value never matches these three.
+    Some(AttrOp::Prefix) => !expected.is_empty() && actual.starts_with(expected),
+    Some(AttrOp::Suffix) => !expected.is_empty() && actual.ends_with(expected),
+    Some(AttrOp::Substring) => !expected.is_empty() && actual.contains(expected),
+  }
+}
+
+// There is no user interaction in this project, so elements are never hovered,
+// focused, active or visited.
+fn matches_pseudo_class(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  pseudo_class: &PseudoClass,
+) -> bool {
+  match *pseudo_class {
+    PseudoClass::Hover | PseudoClass::Focus | PseudoClass::Active | PseudoClass::Visited => false,
+    PseudoClass::Checked => {
+      elem.attributes.contains_key("checked") || elem.attributes.contains_key("selected")
+    }
+    PseudoClass::Disabled => is_form_control(elem) && elem.attributes.contains_key("disabled"),
+    PseudoClass::Enabled => is_form_control(elem) && !elem.attributes.contains_key("disabled"),
+    PseudoClass::FirstChild => preceding_siblings.is_empty(),
+    PseudoClass::Not(ref selector) => !matches_simple_selector(elem, preceding_siblings, selector),
+  }
+}
+
+// Elements that can be disabled.
+fn is_form_control(elem: &ElementData) -> bool {
+  matches!(
+    &*elem.tag_name.to_ascii_lowercase(),
+    "button" | "input" | "select" | "textarea" | "optgroup" | "option" | "fieldset"
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::html_parser;
+
+  fn ids(nodes: Vec<&Node>) -> Vec<&str> {
+    nodes
+      .into_iter()
+      .filter_map(|node| match node.node_type {
+        NodeType::Element(ref elem) => elem.id(),
+        _ => None,
+      })
+      .collect()
+  }
+
+  // Does the first selector in `selectors` match the element with the id `id` in `html`?
+  fn matches_id(selectors: &str, html: &str, id: &str) -> bool {
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let selectors = css_parser::parse_selector_list(selectors.to_string()).unwrap();
+    matches(&selectors[0], root.get_element_by_id(id).unwrap())
+  }
+
+  const LIST: &str = "<ul id=\"u\"><li id=\"a\" class=\"x y\">1</li><li id=\"b\"></li></ul>\
+                      <p id=\"c\"></p>";
+
+  #[test]
+  fn tag_selector_matches_the_tag_name_in_any_case() {
+    assert!(matches_id("li", LIST, "a"));
+    assert!(matches_id("LI", LIST, "a"));
+    assert!(!matches_id("ul", LIST, "a"));
+  }
+
+  #[test]
+  fn id_selector_matches_the_id() {
+    assert!(matches_id("#a", LIST, "a"));
+    assert!(!matches_id("#b", LIST, "a"));
+  }
+
+  #[test]
+  fn class_selector_matches_any_of_the_classes() {
+    assert!(matches_id(".x", LIST, "a"));
+    assert!(matches_id(".y", LIST, "a"));
+    assert!(!matches_id(".x", LIST, "b"));
+  }
+
+  #[test]
+  fn combined_selector_matches_only_if_every_part_does() {
+    assert!(matches_id("li#a.x.y", LIST, "a"));
+    assert!(!matches_id("li#a.z", LIST, "a"));
+    assert!(!matches_id("p#a.x", LIST, "a"));
+  }
+
+  #[test]
+  fn universal_selector_matches_any_element() {
+    assert!(matches_id("*", LIST, "a"));
+    assert!(matches_id("*", LIST, "u"));
+    let root = html_parser::parse(LIST.to_string()).unwrap();
+    let text = &root.get_element_by_id("a").unwrap().children[0];
+    let selectors = css_parser::parse_selector_list("*".to_string()).unwrap();
+    assert!(!matches(&selectors[0], text));
+  }
+
+  #[test]
+  fn combinators_match_the_ancestors_and_siblings_of_a_node() {
+    assert!(matches_id("ul > li + li", LIST, "b"));
+    assert!(matches_id("#u .x", LIST, "a"));
+    assert!(matches_id("#u ~ p", LIST, "c"));
+    assert!(!matches_id("li + li", LIST, "a"));
+    assert!(!matches_id("p li", LIST, "a"));
+  }
+
+  #[test]
+  fn attribute_selectors_match_the_attribute_value() {
+    let html = "<form><a id=\"a\" href=\"/\"></a><a id=\"b\"></a>\
+                <input id=\"c\" type=\"text\"><input id=\"d\" type=\"checkbox\"></form>";
+    assert!(matches_id("a[href]", html, "a"));
+    assert!(!matches_id("a[href]", html, "b"));
+    assert!(matches_id("input[type=\"text\"]", html, "c"));
+    assert!(!matches_id("input[type=\"text\"]", html, "d"));
+    let selectors = css_parser::parse_selector_list("input[type=\"text\"]".to_string()).unwrap();
+    assert_eq!(selectors[0].specificity(), (0, 1, 1));
+  }
+
+  #[test]
+  fn query_selector_all_is_in_document_order_without_duplicates() {
+    let html = "<ul id=\"a\"><li id=\"b\" class=\"x\"></li><li id=\"c\"></li></ul>\
+                <p id=\"d\" class=\"x\"></p>";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    assert_eq!(ids(root.query_selector_all(".x, ul > li, #a")), ["a", "b", "c", "d"]);
+    assert_eq!(ids(root.query_selector("li + li, p").into_iter().collect()), ["c"]);
+    assert!(root.query_selector_all("li >").is_empty());
+  }
+}
@@ -0,0 +1,455 @@
+use super::css_parser::{Color, Declaration, Rule, Specificity, Stylesheet, Value};
+use super::dom::{ElementData, Node, NodeType};
+use super::selector::{matches_in_context, Ancestor, Context};
+use std::collections::HashMap;
+
+// Map from CSS property names to values.
+pub type PropertyMap = HashMap<String, Value>;
+
+// The values of an element's properties after the cascade, with inherited and
+// initial values filled in for the properties that weren't set.
+pub type SpecifiedValues = PropertyMap;
+
+// Properties that take their parent's value when they aren't set.
+// See: https://www.w3.org/TR/CSS2/propidx.html
+const INHERITED_PROPERTIES: &[&str] =
+  &["color", "font-family", "font-size", "font-weight", "visibility"];
+
+// The values properties have when they aren't set and aren't inherited.
+// Only the properties used by this project are listed.
+fn initial_values() -> Vec<(&'static str, Value)> {
+  let keyword = |keyword: &str| Value::Keyword(keyword.to_string());
+  vec![
+    (
+      "color",
+      Value::ColorValue(Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+      }),
+    ),
+    ("display", keyword("inline")),
+    // The initial font family depends on the user agent.
+    ("font-family", keyword("serif")),
+    ("font-size", keyword("medium")),
+    ("font-weight", keyword("normal")),
+    ("visibility", keyword("visible")),
+  ]
+}
+
+fn initial_value(name: &str) -> Option<Value> {
+  initial_values()
+    .into_iter()
+    .find(|&(property, _)| property == name)
+    .map(|(_, value)| value)
+}
+
+// A node with associated style data.
+#[derive(Debug)]
+pub struct StyledNode<'a> {
+  pub node: &'a Node, // pointer to a DOM node.
+  pub specified_values: PropertyMap,
+  pub children: Vec<StyledNode<'a>>,
+}
+
+// The values of the `display` property that are supported.
+// See: https://www.w3.org/TR/CSS2/visuren.html#display-prop
+#[derive(Debug, PartialEq)]
+pub enum Display {
+  Inline,
+  Block,
+  InlineBlock,
+  None,
+}
+
+// The values of the `position` property.
+// See: https://www.w3.org/TR/CSS2/visuren.html#choose-position
+#[derive(Debug, PartialEq)]
+pub enum Position {
+  Static,
+  Relative,
+  Absolute,
+  Fixed,
+}
+
+// The values of the `float` property.
+// See: https://www.w3.org/TR/CSS2/visuren.html#float-position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Float {
+  None,
+  Left,
+  Right,
+}
+
+// The values of the `clear` property, which says which floats a box is placed below.
+// See: https://www.w3.org/TR/CSS2/visuren.html#flow-control
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clear {
+  None,
+  Left,
+  Right,
+  Both,
+}
+
+impl<'a> StyledNode<'a> {
+  // The value of the `position` property, `static` if it isn't set or isn't supported.
+  pub fn position(&self) -> Position {
+    match self.specified_values.get("position") {
+      Some(Value::Keyword(position)) => match &*position.to_ascii_lowercase() {
+        "relative" => Position::Relative,
+        "absolute" => Position::Absolute,
+        "fixed" => Position::Fixed,
+        _ => Position::Static,
+      },
+      _ => Position::Static,
+    }
+  }
+
+  // The value of the `float` property, `none` if it isn't set or isn't supported.
+  pub fn float(&self) -> Float {
+    match self.specified_values.get("float") {
+      Some(Value::Keyword(float)) => match &*float.to_ascii_lowercase() {
+        "left" => Float::Left,
+        "right" => Float::Right,
+        _ => Float::None,
+      },
+      _ => Float::None,
+    }
+  }
+
+  // The value of the `clear` property, `none` if it isn't set or isn't supported.
+  pub fn clear(&self) -> Clear {
+    match self.specified_values.get("clear") {
+      Some(Value::Keyword(clear)) => match &*clear.to_ascii_lowercase() {
+        "left" => Clear::Left,
+        "right" => Clear::Right,
+        "both" => Clear::Both,
+        _ => Clear::None,
+      },
+      _ => Clear::None,
+    }
+  }
+
+  // The value of the `display` property, `inline` if it isn't set or isn't supported.
+  pub fn display(&self) -> Display {
+    match self.specified_values.get("display") {
+      Some(Value::Keyword(display)) => match &*display.to_ascii_lowercase() {
+        "block" => Display::Block,
+        "inline-block" => Display::InlineBlock,
+        "none" => Display::None,
+        _ => Display::Inline,
+      },
+      _ => Display::Inline,
+    }
+  }
+}
+
+// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
+pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+  style_subtree(root, stylesheet, &[], &mut Vec::new(), None)
+}
+
+// Compute the specified values of a single node, inheriting from its parent element and
+// matching combinators against the elements around it. Nodes other than elements have
+// no values.
+pub fn specified_values(node: &Node, stylesheet: &Stylesheet) -> SpecifiedValues {
+  match node.node_type {
+    NodeType::Element(ref elem) => {
+      let parent_values = node
+        .parent()
+        .filter(|parent| matches!(parent.node_type, NodeType::Element(_)))
+        .map(|parent| specified_values(&parent, stylesheet));
+      let context = Context::of(node);
+      element_values(
+        elem,
+        &context.preceding_siblings(),
+        &context.ancestors(),
+        stylesheet,
+        parent_values.as_ref(),
+      )
+    }
+    _ => HashMap::new(),
+  }
+}
+
+// `preceding_siblings` holds the elements before `node` in its parent, nearest last,
+// `ancestors` holds every element above `node`, nearest last, and `parent_values` holds
+// the specified values of its parent element.
+fn style_subtree<'a>(
+  node: &'a Node,
+  stylesheet: &'a Stylesheet,
+  preceding_siblings: &[&'a ElementData],
+  ancestors: &mut Vec<Ancestor<'a>>,
+  parent_values: Option<&SpecifiedValues>,
+) -> StyledNode<'a> {
+  match node.node_type {
+    NodeType::Element(ref elem) => {
+      let specified_values =
+        element_values(elem, preceding_siblings, ancestors, stylesheet, parent_values);
+      ancestors.push(Ancestor {
+        elem,
+        preceding_siblings: preceding_siblings.to_vec(),
+      });
+      let children = style_children(node, stylesheet, ancestors, Some(&specified_values));
+      ancestors.pop();
+      StyledNode {
+        node,
+        specified_values,
+        children,
+      }
+    }
+    // The document has no style of its own, and its elements are styled as roots.
+    NodeType::Document(_) => StyledNode {
+      node,
+      specified_values: HashMap::new(),
+      children: style_children(node, stylesheet, ancestors, None),
+    },
+    // Text has no style of its own, but it takes its parent's inherited properties, such
+    // as the font size that inline layout measures it with.
+    NodeType::Text(_) => StyledNode {
+      node,
+      specified_values: INHERITED_PROPERTIES
+        .iter()
+        .filter_map(|&name| Some((name.to_string(), parent_values?.get(name)?.clone())))
+        .collect(),
+      children: Vec::new(),
+    },
+    _ => StyledNode {
+      node,
+      specified_values: HashMap::new(),
+      children: Vec::new(),
+    },
+  }
+}
+
+// Style the children of `node`, keeping track of the elements that come before each one.
+fn style_children<'a>(
+  node: &'a Node,
+  stylesheet: &'a Stylesheet,
+  ancestors: &mut Vec<Ancestor<'a>>,
+  parent_values: Option<&SpecifiedValues>,
+) -> Vec<StyledNode<'a>> {
+  let mut preceding_siblings = Vec::new();
+  let mut children = Vec::new();
+  for child in &node.children {
+    children.push(style_subtree(
+      child,
+      stylesheet,
+      &preceding_siblings,
+      ancestors,
+      parent_values,
+    ));
+    if let NodeType::Element(ref child_elem) = child.node_type {
+      preceding_siblings.push(child_elem);
+    }
+  }
+  children
+}
+
+// Apply styles to a single element, returning the specified values.
+fn element_values(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  ancestors: &[Ancestor],
+  stylesheet: &Stylesheet,
+  parent_values: Option<&SpecifiedValues>,
+) -> SpecifiedValues {
+  let inherited = |name: &str| parent_values.and_then(|values| values.get(name)).cloned();
+  let mut values: SpecifiedValues =
+    cascade(matching_declarations(elem, preceding_siblings, ancestors, stylesheet))
+      .into_iter()
+      .filter_map(|(name, value)| {
+        // The `inherit` and `initial` keywords can be used with any property.
+        let value = match *value {
+          Value::Keyword(ref keyword) if keyword.eq_ignore_ascii_case("inherit") => {
+            inherited(&name).or_else(|| initial_value(&name))?
+          }
+          Value::Keyword(ref keyword) if keyword.eq_ignore_ascii_case("initial") => {
+            initial_value(&name)?
+          }
+          _ => value.clone(),
+        };
+        Some((name, value))
+      })
+      .collect();
+  for &name in INHERITED_PROPERTIES {
+    if !values.contains_key(name) {
+      if let Some(value) = inherited(name) {
+        values.insert(name.to_string(), value);
+      }
+    }
+  }
+  for (name, value) in initial_values() {
+    values.entry(name.to_string()).or_insert(value);
+  }
+  resolve_current_color(&mut values, parent_values);
+  values
+}
+
+// Replace the `currentColor` keyword with the element's `color`.
+// In `color` itself it means the parent's color, like `inherit`.
+fn resolve_current_color(values: &mut SpecifiedValues, parent_values: Option<&SpecifiedValues>) {
+  let is_current_color = |value: &Value| match *value {
+    Value::Keyword(ref keyword) => keyword.eq_ignore_ascii_case("currentColor"),
+    _ => false,
+  };
+  if values.get("color").is_some_and(is_current_color) {
+    let color = parent_values
+      .and_then(|values| values.get("color").cloned())
+      .or_else(|| initial_value("color"));
+    if let Some(color) = color {
+      values.insert("color".to_string(), color);
+    }
+  }
+  let color = match values.get("color") {
+    Some(color) => color.clone(),
+    None => return,
+  };
+  for value in values.values_mut() {
+    if is_current_color(value) {
+      *value = color.clone();
+    }
+  }
+}
+
+// A declaration that applies to an element, with the specificity of the selector that
+// matched and its index in the stylesheet, which gives the source order.
+pub type MatchedDeclaration<'a> = (&'a Declaration, Specificity, usize);
+
+// Find all declarations in `stylesheet` that apply to `node`, matching combinators
+// against the elements around it.
+pub fn match_rules<'a>(node: &Node, stylesheet: &'a Stylesheet) -> Vec<MatchedDeclaration<'a>> {
+  match node.node_type {
+    NodeType::Element(ref elem) => {
+      let context = Context::of(node);
+      matching_declarations(elem, &context.preceding_siblings(), &context.ancestors(), stylesheet)
+    }
+    _ => Vec::new(),
+  }
+}
+
+// Pick the winning value for each property. `!important` declarations beat normal ones,
+// then the declaration with the highest specificity wins, and then the one that comes
+// last in the stylesheet.
+// See: https://www.w3.org/TR/css-cascade-4/#cascade-sort
+pub fn cascade<'a>(mut matches: Vec<MatchedDeclaration<'a>>) -> HashMap<String, &'a Value> {
+  matches.sort_by_key(|&(declaration, specificity, index)| {
+    (declaration.important, specificity, index)
+  });
+  matches
+    .into_iter()
+    .map(|(declaration, _, _)| (declaration.name.clone(), &declaration.value))
+    .collect()
+}
+
+// Find all declarations that apply to the given element.
+// Media queries aren't evaluated, so rules inside at-rules are not applied.
+fn matching_declarations<'a>(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  ancestors: &[Ancestor],
+  stylesheet: &'a Stylesheet,
+) -> Vec<MatchedDeclaration<'a>> {
+  let mut matches = Vec::new();
+  let mut index = 0;
+  for rule in stylesheet.style_rules() {
+    let specificity = match_rule(elem, preceding_siblings, ancestors, rule);
+    for declaration in &rule.declarations {
+      if let Some(specificity) = specificity {
+        matches.push((declaration, specificity, index));
+      }
+      index += 1;
+    }
+  }
+  matches
+}
+
+// If `rule` matches `elem`, return the specificity of its most specific matching
+// selector. Otherwise return `None`.
+fn match_rule(
+  elem: &ElementData,
+  preceding_siblings: &[&ElementData],
+  ancestors: &[Ancestor],
+  rule: &Rule,
+) -> Option<Specificity> {
+  // Find the first (most specific) matching selector.
+  rule
+    .selectors
+    .iter()
+    .find(|selector| matches_in_context(selector, elem, preceding_siblings, ancestors))
+    .map(|selector| selector.specificity())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{css_parser, html_parser};
+
+  // The specified values of the first element named `tag`, styled with `css`.
+  fn values_of(html: &str, css: &str, tag: &str) -> SpecifiedValues {
+    fn find<'a>(styled: &'a StyledNode<'a>, tag: &str) -> Option<&'a StyledNode<'a>> {
+      match styled.node.node_type {
+        NodeType::Element(ref elem) if elem.tag_name == tag => Some(styled),
+        _ => styled.children.iter().find_map(|child| find(child, tag)),
+      }
+    }
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let styled = style_tree(&root, &stylesheet);
+    find(&styled, tag).unwrap().specified_values.clone()
+  }
+
+  fn color(r: u8, g: u8, b: u8) -> Value {
+    Value::ColorValue(Color { r, g, b, a: 255 })
+  }
+
+  #[test]
+  fn inherit_and_initial_ignore_case() {
+    let css = "div { color: #00f; display: block } p { color: INHERIT; display: Initial }";
+    let values = values_of("<div><p></p></div>", css, "p");
+    assert_eq!(values.get("color"), Some(&color(0, 0, 255)));
+    assert_eq!(values.get("display"), Some(&Value::Keyword("inline".to_string())));
+  }
+
+  // The winning color for the element with the id `id` in `html`, by `match_rules`.
+  fn cascaded_color(html: &str, css: &str, id: &str) -> Option<Value> {
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let node = root.get_element_by_id(id).unwrap();
+    cascade(match_rules(node, &stylesheet)).get("color").map(|&value| value.clone())
+  }
+
+  #[test]
+  fn cascade_picks_the_highest_specificity_then_the_last_rule() {
+    let html = "<div class=\"d\"><p id=\"a\" class=\"b\"></p></div>";
+    let css = ".b { color: #0f0 } p { color: #f00 } div > p { color: #00f }";
+    assert_eq!(cascaded_color(html, css, "a"), Some(color(0, 255, 0)));
+    let css = "div > p { color: #00f } .d p { color: #f00 } p.b { color: #0f0 }";
+    assert_eq!(cascaded_color(html, css, "a"), Some(color(0, 255, 0)));
+    let css = "p.b { color: #0f0 } .d > p { color: #f00 }";
+    assert_eq!(cascaded_color(html, css, "a"), Some(color(255, 0, 0)));
+    let css = ".d > p { color: #f00 } p.b { color: #0f0 }";
+    assert_eq!(cascaded_color(html, css, "a"), Some(color(0, 255, 0)));
+  }
+
+  #[test]
+  fn specified_values_inherit_from_the_parent() {
+    let html = "<div class=\"d\"><p>a</p><p id=\"a\"></p></div>";
+    let css = "div { color: #00f; display: block } p + p { margin-top: 0 }";
+    let root = html_parser::parse(html.to_string()).unwrap();
+    let stylesheet = css_parser::parse(css.to_string()).unwrap();
+    let values = specified_values(root.get_element_by_id("a").unwrap(), &stylesheet);
+    assert_eq!(values.get("color"), Some(&color(0, 0, 255)));
+    assert_eq!(values.get("display"), Some(&Value::Keyword("inline".to_string())));
+    assert!(values.contains_key("margin-top"));
+  }
+
+  #[test]
+  fn important_beats_a_more_specific_selector() {
+    let css = "p { color: #f00 !important } #a.b { color: #00f }";
+    let values = values_of("<p id=\"a\" class=\"b\"></p>", css, "p");
+    assert_eq!(values.get("color"), Some(&color(255, 0, 0)));
+  }
+}
+